@@ -0,0 +1,265 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::building::BuildingType;
+use crate::components::PlayerResources;
+use crate::players::Players;
+
+/// Path of the balance file within the assets directory.
+pub const BALANCE_ASSET: &str = "balance.ron";
+
+/// Path of the balance file for programs without an asset server, like the dedicated server.
+pub const BALANCE_FILE: &str = "assets/balance.ron";
+
+/// Indices of the sprites in the sprite sheet.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct SpriteIndices {
+    pub planet: u32,
+    pub moon: u32,
+    pub rocket: u32,
+    pub mining_moon: u32,
+    pub production_moon: u32,
+    /// Shown at the cursor while placing a mining building
+    pub mining_cursor: u32,
+    /// Shown at the cursor while placing a production building
+    pub production_cursor: u32,
+}
+
+/// The numbers the game is played with.
+///
+/// The server sends its balance to all players at the start of a match, so that everybody plays
+/// with the same numbers, no matter what their own balance file says.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, TypeUuid)]
+#[uuid = "6f0b7c5e-3a52-4d8e-9a51-2f43c1d5e8b7"]
+pub struct Balance {
+    /// Pink crystals every player starts the match with
+    pub starting_pink: u32,
+    pub mining_cost: u32,
+    pub production_cost: u32,
+    /// Seconds between two yields of a mining moon
+    pub mining_interval: f32,
+    /// Pink crystals a mining moon yields at a time
+    pub mining_yield: u32,
+    pub rocket_cost: u32,
+    pub rocket_speed: f32,
+    /// Hit points a planet loses when hit by a rocket
+    pub rocket_damage: u32,
+    /// Distance from its launch position at which a rocket disappears
+    pub rocket_range: f32,
+    /// Seconds after which a rocket disappears, e.g. when caught in an orbit
+    pub rocket_lifetime: f32,
+    /// Strength of a planet's gravity, as the acceleration of rockets at distance 1
+    pub planet_gravity: f32,
+    /// Strength of a moon's gravity, as the acceleration of rockets at distance 1
+    pub moon_gravity: f32,
+    pub sprites: SpriteIndices,
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Self {
+            starting_pink: 30,
+            mining_cost: 20,
+            production_cost: 15,
+            mining_interval: 1.0,
+            mining_yield: 1,
+            rocket_cost: 3,
+            rocket_speed: 300.0,
+            rocket_damage: 10,
+            rocket_range: 2000.0,
+            rocket_lifetime: 15.0,
+            planet_gravity: 2_000_000.0,
+            moon_gravity: 500_000.0,
+            sprites: SpriteIndices {
+                planet: 0,
+                moon: 1,
+                rocket: 7,
+                mining_moon: 9,
+                production_moon: 8,
+                mining_cursor: 5,
+                production_cursor: 12,
+            },
+        }
+    }
+}
+
+impl Balance {
+    /// Reads the balance from the given RON file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        ron::de::from_str(&contents).map_err(|e| format!("Invalid balance file: {}", e))
+    }
+
+    /// Returns a fingerprint of all numbers, which tells whether two balances are the same.
+    pub fn hash(&self) -> u64 {
+        // FNV-1a, which unlike the standard library's hasher is the same for every build
+        bincode::serialize(self)
+            .unwrap()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    /// Pink crystals it costs to build the given building.
+    pub fn building_cost(&self, building: BuildingType) -> u32 {
+        match building {
+            BuildingType::Mining => self.mining_cost,
+            BuildingType::Production => self.production_cost,
+        }
+    }
+
+    /// Returns the pink crystals the given number of mining moons produce per minute.
+    pub fn mining_rate(&self, mining_moons: usize) -> f32 {
+        if self.mining_interval <= 0.0 {
+            return 0.0;
+        }
+        mining_moons as f32 * self.mining_yield as f32 * 60.0 / self.mining_interval
+    }
+
+    /// Sprite of a moon with the given building.
+    pub fn building_moon_texture_index(&self, building: BuildingType) -> u32 {
+        match building {
+            BuildingType::Mining => self.sprites.mining_moon,
+            BuildingType::Production => self.sprites.production_moon,
+        }
+    }
+
+    /// Sprite following the cursor while placing the given building.
+    pub fn building_cursor_texture_index(&self, building: BuildingType) -> u32 {
+        match building {
+            BuildingType::Mining => self.sprites.mining_cursor,
+            BuildingType::Production => self.sprites.production_cursor,
+        }
+    }
+
+    pub fn starting_resources(&self) -> PlayerResources {
+        PlayerResources {
+            pink: self.starting_pink,
+            green: 0,
+            spent: HashMap::new(),
+            unaffordable: 0,
+        }
+    }
+}
+
+/// Loads balance files from the assets directory.
+#[derive(Default)]
+pub struct BalanceLoader;
+
+impl AssetLoader for BalanceLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let balance: Balance = ron::de::from_bytes(bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(balance));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+/// Handle keeping the local balance file loaded.
+pub struct BalanceHandle(pub Handle<Balance>);
+
+impl FromResources for BalanceHandle {
+    fn from_resources(resources: &Resources) -> Self {
+        let asset_server = resources.get::<AssetServer>().unwrap();
+        // changes to the balance file are picked up while developing
+        if cfg!(debug_assertions) {
+            if let Err(e) = asset_server.watch_for_changes() {
+                warn!("Cannot watch the balance file for changes: {:?}", e);
+            }
+        }
+        BalanceHandle(asset_server.load(BALANCE_ASSET))
+    }
+}
+
+/// Fingerprint of the local balance file, once it has been loaded.
+#[derive(Default)]
+pub struct LocalBalanceHash(pub Option<u64>);
+
+impl LocalBalanceHash {
+    /// Warns if the balance the server sent for a match is not the one of the local balance file,
+    /// e.g. because the server or the player has an outdated version of the game.
+    pub fn check(&self, server_hash: u64) {
+        match self.0 {
+            Some(local_hash) if local_hash != server_hash => warn!(
+                "The server plays with balance {:016x}, but the local balance file is {:016x}",
+                server_hash, local_hash
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// This plugin loads the local balance file, which is used until a match starts.
+/// The numbers of a running match are never changed, they are the server's.
+pub struct BalancePlugin;
+
+impl Plugin for BalancePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_asset::<Balance>()
+            .init_asset_loader::<BalanceLoader>()
+            .init_resource::<Balance>()
+            .init_resource::<BalanceHandle>()
+            .init_resource::<LocalBalanceHash>()
+            .add_system(reload_balance);
+    }
+}
+
+#[derive(Default)]
+pub struct ReloadBalanceState {
+    asset_event_reader: EventReader<AssetEvent<Balance>>,
+}
+
+/// System adopting the local balance file whenever it was (re)loaded outside of a match.
+fn reload_balance(
+    mut state: Local<ReloadBalanceState>,
+    asset_events: Res<Events<AssetEvent<Balance>>>,
+    assets: Res<Assets<Balance>>,
+    handle: Res<BalanceHandle>,
+    players: Res<Players>,
+    mut balance: ResMut<Balance>,
+    mut local_hash: ResMut<LocalBalanceHash>,
+) {
+    for event in state.asset_event_reader.iter(&asset_events) {
+        let changed = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *changed != handle.0 {
+            continue;
+        }
+        let loaded = match assets.get(changed) {
+            Some(loaded) => loaded,
+            None => continue,
+        };
+        local_hash.0 = Some(loaded.hash());
+        if !players.is_empty() {
+            warn!("Ignoring changes to the balance file during a match");
+            continue;
+        }
+        info!("Loaded balance {:016x}", loaded.hash());
+        *balance = loaded.clone();
+    }
+}
@@ -0,0 +1,385 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::{self, BufWriter, Write},
+    rc::Rc,
+    time::Duration,
+};
+
+use bevy::{
+    app::stage,
+    core::DefaultTaskPoolOptions,
+    prelude::*,
+    transform::TransformPlugin,
+};
+use serde::Serialize;
+
+use moonshot::balance::{Balance, BALANCE_FILE};
+use moonshot::bot::{BotPlugin, Difficulty, Strategy};
+use moonshot::components::PlayerResources;
+use moonshot::network::{
+    GameInfo, GameSettings, LobbyPlayer, LobbyResponse, LoopbackBackend, MatchTeams,
+    NetworkPlugin, NetworkSimulationTime, TeamMode, MAPS,
+};
+use moonshot::players::Players;
+use moonshot::simulation::SimulationPlugin;
+
+/// Seconds of game time every update of the simulated apps advances by.
+const TIME_STEP: f64 = 1.0 / 60.0;
+
+/// Seconds of game time between two samples of the players' crystals.
+const SAMPLE_INTERVAL: f32 = 10.0;
+
+/// A bot taking part in the matches, given as `<difficulty>:<strategy>` on the command line.
+#[derive(Clone, Debug)]
+struct BotConfig {
+    label: String,
+    difficulty: Difficulty,
+    strategy: Strategy,
+}
+
+impl BotConfig {
+    fn parse(label: &str) -> Result<Self, String> {
+        let mut parts = label.splitn(2, ':');
+        let difficulty = parts.next().unwrap_or_default().parse()?;
+        let strategy = parts.next().unwrap_or("economy").parse()?;
+        Ok(Self {
+            label: label.to_string(),
+            difficulty,
+            strategy,
+        })
+    }
+}
+
+/// What the batch of matches is made of.
+struct BatchOptions {
+    bots: Vec<BotConfig>,
+    map: String,
+    matches: u32,
+    seed: u64,
+    /// Seconds of game time after which a match counts as a draw
+    max_length: f32,
+    /// Balance file the matches are played with, read again for every match
+    balance: String,
+    /// Path of the written reports, without file extension
+    out: String,
+}
+
+impl BatchOptions {
+    fn from_args() -> Result<Self, String> {
+        let arg = |name: &str| std::env::args().skip_while(|arg| arg != name).nth(1);
+        let number = |name: &str, default: &str| {
+            let value = arg(name).unwrap_or_else(|| default.to_string());
+            value.parse().map_err(|_| format!("Invalid value for {}: {}", name, value))
+        };
+
+        let bots = arg("--bots")
+            .unwrap_or_else(|| "normal:economy,normal:rush".to_string())
+            .split(',')
+            .map(BotConfig::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if bots.len() < 2 {
+            return Err("A match needs at least two bots".to_string());
+        }
+        let map = arg("--map").unwrap_or_else(|| MAPS[0].to_string());
+        if !MAPS.contains(&map.as_str()) {
+            return Err(format!("Unknown map: {}", map));
+        }
+        Ok(Self {
+            bots,
+            map,
+            matches: number("--matches", "10")?,
+            seed: number("--seed", "1")?,
+            max_length: number("--max-length", "900")?,
+            balance: arg("--balance").unwrap_or_else(|| BALANCE_FILE.to_string()),
+            out: arg("--out").unwrap_or_else(|| "balance".to_string()),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct PlayerResult {
+    player: u8,
+    bot: String,
+    won: bool,
+    eliminated: bool,
+    /// Pink crystals of the player, sampled every `sample_interval` seconds
+    pink: Vec<u32>,
+}
+
+#[derive(Serialize)]
+struct MatchResult {
+    index: u32,
+    seed: u64,
+    map: String,
+    balance_hash: u64,
+    /// Seconds of game time the match lasted
+    length: f32,
+    timed_out: bool,
+    winning_team: Option<u8>,
+    players: Vec<PlayerResult>,
+}
+
+#[derive(Serialize)]
+struct BotSummary {
+    bot: String,
+    matches: u32,
+    wins: u32,
+    win_rate: f32,
+}
+
+#[derive(Serialize)]
+struct BatchReport {
+    sample_interval: f32,
+    summary: Vec<BotSummary>,
+    matches: Vec<MatchResult>,
+}
+
+/// Runs many headless matches between bots as fast as possible and reports how they went.
+///
+/// Usage: `bevy_balance [--bots normal:economy,hard:rush] [--map <name>] [--matches <n>]
+/// [--seed <n>] [--max-length <seconds>] [--balance <file>] [--out <path>]`
+///
+/// Writes a row per player and match to `<path>.csv`, their crystals over time to
+/// `<path>_resources.csv`, and everything including the win rates to `<path>.json`.
+fn main() {
+    let options = BatchOptions::from_args().unwrap();
+
+    let mut results = Vec::new();
+    for index in 0..options.matches {
+        let result = run_match(&options, index);
+        println!(
+            "Match {} ended after {:.0}s, winning team {:?}",
+            index, result.length, result.winning_team
+        );
+        results.push(result);
+    }
+
+    let report = BatchReport {
+        sample_interval: SAMPLE_INTERVAL,
+        summary: summarize(&options.bots, &results),
+        matches: results,
+    };
+    for summary in &report.summary {
+        println!(
+            "{}: won {} of {} matches ({:.0}%)",
+            summary.bot,
+            summary.wins,
+            summary.matches,
+            summary.win_rate * 100.0
+        );
+    }
+    if let Err(e) = write_report(&options.out, &report) {
+        eprintln!("Failed to write report: {}", e);
+    }
+}
+
+/// Runs a single match between all bots, with the seats rotating from match to match.
+fn run_match(options: &BatchOptions, index: u32) -> MatchResult {
+    let seed = options.seed.wrapping_add(index as u64);
+    let balance = Balance::load(&options.balance).unwrap();
+    let balance_hash = balance.hash();
+    let seats: Vec<&BotConfig> = (0..options.bots.len())
+        .map(|seat| &options.bots[(seat + index as usize) % options.bots.len()])
+        .collect();
+    let game = GameInfo {
+        id: index,
+        settings: GameSettings {
+            name: format!("Balance Match {}", index),
+            map: options.map.clone(),
+            player_count: seats.len() as u8,
+            teams: TeamMode::FreeForAll,
+            friendly_fire: false,
+        },
+        players: seats
+            .iter()
+            .map(|bot| LobbyPlayer {
+                name: bot.label.clone(),
+                ready: true,
+                team: 0,
+            })
+            .collect(),
+    };
+
+    // players are numbered in the order they connect to the server
+    let server_backend = LoopbackBackend::new();
+    let bot_backends: Vec<_> = seats.iter().map(|_| server_backend.connect()).collect();
+    let teams = MatchTeams::new((0..seats.len()).map(|player| game.team(player)).collect());
+    let mut server = steppable(
+        headless_app()
+            .add_plugin(NetworkPlugin::server(server_backend))
+            .add_resource(teams),
+    );
+    let mut bots: Vec<App> = bot_backends
+        .into_iter()
+        .zip(&seats)
+        .enumerate()
+        .map(|(player, (backend, bot))| {
+            let app = steppable(
+                headless_app()
+                    .add_plugin(AssetPlugin)
+                    .add_plugin(TransformPlugin)
+                    .add_asset::<TextureAtlas>()
+                    .add_plugin(NetworkPlugin::client(backend))
+                    .add_plugin(SimulationPlugin)
+                    .add_resource(balance.clone())
+                    .add_plugin(BotPlugin {
+                        difficulty: bot.difficulty,
+                        strategy: bot.strategy,
+                        seed: seed.wrapping_mul(31).wrapping_add(player as u64),
+                    }),
+            );
+            // the match starts right away instead of waiting in a lobby
+            let mut lobby_events = app.resources.get_mut::<Events<LobbyResponse>>().unwrap();
+            lobby_events.send(LobbyResponse::MatchStarted {
+                game: game.clone(),
+                player: player as u8,
+                balance: balance.clone(),
+                balance_hash,
+            });
+            drop(lobby_events);
+            app
+        })
+        .collect();
+
+    let mut pink = vec![Vec::new(); seats.len()];
+    let mut length = 0.0;
+    let timed_out = loop {
+        server.update();
+        for bot in &mut bots {
+            bot.update();
+        }
+
+        let sim_time = bots[0].resources.get::<NetworkSimulationTime>().unwrap();
+        length = sim_time.frame_number() as f32 * sim_time.per_frame_duration();
+        drop(sim_time);
+        if length >= pink[0].len() as f32 * SAMPLE_INTERVAL {
+            for (curve, bot) in pink.iter_mut().zip(&bots) {
+                curve.push(bot.resources.get::<PlayerResources>().unwrap().pink);
+            }
+        }
+
+        if bots[0].resources.get::<Players>().unwrap().is_match_over() {
+            break false;
+        }
+        if length >= options.max_length {
+            break true;
+        }
+    };
+
+    let players = bots[0].resources.get::<Players>().unwrap();
+    let winning_team = players.winning_team();
+    MatchResult {
+        index,
+        seed,
+        map: options.map.clone(),
+        balance_hash,
+        length,
+        timed_out,
+        winning_team,
+        players: seats
+            .iter()
+            .zip(pink)
+            .enumerate()
+            .map(|(player, (bot, pink))| PlayerResult {
+                player: player as u8,
+                bot: bot.label.clone(),
+                won: winning_team == Some(game.team(player)),
+                eliminated: players.is_eliminated(player as u8),
+                pink,
+            })
+            .collect(),
+    }
+}
+
+/// Creates an app without any graphics whose clock advances by a fixed step on every update.
+fn headless_app() -> AppBuilder {
+    let mut app = App::build();
+    app.add_resource(DefaultTaskPoolOptions::with_num_threads(1))
+        .add_plugins(MinimalPlugins)
+        .add_system_to_stage(stage::PRE_UPDATE, fixed_time_step);
+    app
+}
+
+/// Finishes building the app without running its loop, so that it can be updated step by step.
+fn steppable(app: &mut AppBuilder) -> App {
+    let slot = Rc::new(RefCell::new(None));
+    let runner_slot = slot.clone();
+    app.set_runner(move |app| *runner_slot.borrow_mut() = Some(app)).run();
+    let app = slot.borrow_mut().take();
+    app.unwrap()
+}
+
+/// System replacing the wall clock by a fixed step per update, so matches run as fast as
+/// possible while every app sees the same time passing.
+fn fixed_time_step(mut clock: Local<f64>, mut time: ResMut<Time>) {
+    *clock += TIME_STEP;
+    time.delta = Duration::from_secs_f64(TIME_STEP);
+    time.delta_seconds_f64 = TIME_STEP;
+    time.delta_seconds = TIME_STEP as f32;
+    time.seconds_since_startup = *clock;
+}
+
+fn summarize(bots: &[BotConfig], results: &[MatchResult]) -> Vec<BotSummary> {
+    bots.iter()
+        .enumerate()
+        // bots given several times are summarized together
+        .filter(|&(index, bot)| bots[..index].iter().all(|other| other.label != bot.label))
+        .map(|(_, bot)| {
+            let played = results.iter().flat_map(|r| &r.players).filter(|p| p.bot == bot.label);
+            let (matches, wins) = played.fold((0, 0), |(matches, wins), p| {
+                (matches + 1, wins + p.won as u32)
+            });
+            BotSummary {
+                bot: bot.label.clone(),
+                matches,
+                wins,
+                win_rate: if matches > 0 { wins as f32 / matches as f32 } else { 0.0 },
+            }
+        })
+        .collect()
+}
+
+fn write_report(out: &str, report: &BatchReport) -> io::Result<()> {
+    let mut players_csv = BufWriter::new(File::create(format!("{}.csv", out))?);
+    writeln!(
+        players_csv,
+        "match,seed,map,balance,length,timed_out,player,bot,won,eliminated,pink"
+    )?;
+    let mut resources_csv = BufWriter::new(File::create(format!("{}_resources.csv", out))?);
+    writeln!(resources_csv, "match,player,bot,time,pink")?;
+    for result in &report.matches {
+        for player in &result.players {
+            writeln!(
+                players_csv,
+                "{},{},{},{:016x},{:.1},{},{},{},{},{},{}",
+                result.index,
+                result.seed,
+                result.map,
+                result.balance_hash,
+                result.length,
+                result.timed_out,
+                player.player,
+                player.bot,
+                player.won,
+                player.eliminated,
+                player.pink.last().copied().unwrap_or(0)
+            )?;
+            for (sample, pink) in player.pink.iter().enumerate() {
+                let time = sample as f32 * report.sample_interval;
+                writeln!(
+                    resources_csv,
+                    "{},{},{},{},{}",
+                    result.index, player.player, player.bot, time, pink
+                )?;
+            }
+        }
+    }
+
+    let json = BufWriter::new(File::create(format!("{}.json", out))?);
+    serde_json::to_writer_pretty(json, report)?;
+    Ok(())
+}
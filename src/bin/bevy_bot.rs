@@ -0,0 +1,63 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{app::ScheduleRunnerSettings, log::LogPlugin, prelude::*, transform::TransformPlugin};
+
+use moonshot::balance::BalancePlugin;
+use moonshot::bot::{BotPlugin, Difficulty, Strategy};
+use moonshot::network::{
+    ConditionedBackend, NetworkBackend, NetworkConditions, NetworkPlugin, TcpBackend,
+};
+use moonshot::simulation::SimulationPlugin;
+
+const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:7777";
+
+/// Runs an AI player without any graphics, which joins the first open game on the server.
+///
+/// Usage: `bevy_bot [--server <addr>] [--difficulty easy|normal|hard] [--strategy economy|rush]`,
+/// followed by the network conditions to simulate, if any.
+fn main() {
+    let addr: SocketAddr = std::env::args()
+        .skip_while(|arg| arg != "--server")
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_SERVER_ADDR.to_string())
+        .parse()
+        .expect("Invalid server address");
+    let difficulty = Difficulty::from_args(std::env::args()).unwrap();
+    let strategy = Strategy::from_args(std::env::args()).unwrap();
+    let conditions = NetworkConditions::from_args(std::env::args()).unwrap();
+    // every bot decides a little differently, unless their seed is given
+    let seed = std::env::args()
+        .skip_while(|arg| arg != "--bot-seed")
+        .nth(1)
+        .map(|seed| seed.parse().expect("Invalid bot seed"))
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64);
+
+    let mut backend: Box<dyn NetworkBackend> = Box::new(TcpBackend::connect(addr).unwrap());
+    if let Some(conditions) = conditions {
+        backend = Box::new(ConditionedBackend::new(backend, conditions));
+    }
+
+    App::build()
+        .add_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(1.0 / 60.0)))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(LogPlugin)
+        .add_plugin(AssetPlugin)
+        .add_plugin(TransformPlugin)
+        // the world is spawned as sprites, even though nobody looks at them
+        .add_asset::<TextureAtlas>()
+        .add_plugin(NetworkPlugin::client(backend))
+        .add_plugin(SimulationPlugin)
+        .add_plugin(BalancePlugin)
+        .add_plugin(BotPlugin {
+            difficulty,
+            strategy,
+            seed,
+        })
+        .run();
+}
@@ -1,19 +1,39 @@
 // Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
+use std::{io, net::ToSocketAddrs};
+
 use bevy::{
-    input::{keyboard::KeyboardInput, ElementState, Input},
     log::{Level, LogSettings},
     prelude::*,
     render::{camera::Camera, pass::ClearColor},
     ui::camera::UI_CAMERA,
 };
 
+use moonshot::balance::{Balance, BalancePlugin};
 use moonshot::building::*;
+use moonshot::camera::{CameraGoal, CameraPlugin};
+use moonshot::chat::*;
 use moonshot::combat::*;
+use moonshot::command_bar::{CommandBarPlugin, GameCommand};
 use moonshot::components::*;
 use moonshot::cursor_world_coords::*;
-use moonshot::network::{NetworkPlugin, PlayerAction, Transport};
+use moonshot::gamepad::GamepadPlugin;
+use moonshot::input_map::{InputAction, InputActions, InputMapPlugin};
+use moonshot::join_screen::*;
+use moonshot::lobby_screen::*;
+use moonshot::map::*;
+use moonshot::minimap::MinimapPlugin;
+use moonshot::network::{
+    ConditionedBackend, ConnectionStats, Network, NetworkBackend, NetworkConditions,
+    NetworkPlugin, NetworkSimulationTime, PendingActions, PlayerAction, TcpBackend, TimeControl,
+    Transport, UdpBackend, GAME_SPEEDS, MAX_PAUSES,
+};
+use moonshot::picking::*;
+use moonshot::players::Players;
+use moonshot::resource_hud::ResourceHudPlugin;
+use moonshot::selection::{Selection, SelectionPlugin};
+use moonshot::simulation::SimulationPlugin;
 
 struct GamePlugin;
 
@@ -21,19 +41,46 @@ impl Plugin for GamePlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.add_resource(ClearColor(Color::hex("22265A").unwrap()))
             .add_resource(CursorInWorld::default())
-            .add_resource(PlayerResources { pink: 30, green: 0 })
-            .add_startup_system(game_setup)
+            .init_resource::<RocketAim>()
+            .init_resource::<AimMaterials>()
+            .add_startup_system(setup)
+            .add_system(game_setup)
             .add_system(cursor_world_coords)
-            .add_system(camera_motion)
-            .add_system(kepler_motion)
             .add_system(building)
             .add_system(planet_auras)
             .add_system(combat)
-            .add_system(resource_mining);
+            .add_system(aim_preview)
+            .add_system(network_stats_text)
+            .add_system(match_outcome_text)
+            .add_system(time_controls)
+            .add_system(time_control_text);
     }
 }
 
+/// How connections to the server chosen on the join screen are made.
+struct ConnectionOptions {
+    udp: bool,
+    conditions: Option<NetworkConditions>,
+    /// Server given with `--server <addr>`, which is joined right away instead of one in the
+    /// local network
+    server: Option<String>,
+}
+
 fn main() {
+    let server = std::env::args().skip_while(|arg| arg != "--server").nth(1);
+    let conditions = match NetworkConditions::from_args(std::env::args()) {
+        Ok(conditions) => conditions,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let options = ConnectionOptions {
+        udp: std::env::args().any(|arg| arg == "--udp"),
+        conditions,
+        server,
+    };
+
     App::build()
         .add_resource(WindowDescriptor {
             title: "Moonshot!".to_string(),
@@ -45,241 +92,330 @@ fn main() {
             level: Level::DEBUG,
             ..Default::default()
         })
+        .add_resource(options)
         .add_plugins(DefaultPlugins)
         .add_plugin(GamePlugin)
-        .add_plugin(NetworkPlugin)
+        .add_plugin(SimulationPlugin)
+        .add_plugin(BalancePlugin)
+        .add_plugin(InputMapPlugin)
+        .add_plugin(PickingPlugin)
+        .add_plugin(SelectionPlugin)
+        .add_plugin(CommandBarPlugin)
+        .add_plugin(ResourceHudPlugin)
+        .add_plugin(CameraPlugin)
+        .add_plugin(GamepadPlugin)
+        .add_plugin(MinimapPlugin)
+        .add_plugin(NetworkPlugin::disconnected_client())
+        .add_plugin(LobbyScreenPlugin)
+        .add_plugin(JoinScreenPlugin)
+        .add_plugin(ChatPlugin)
+        .add_startup_system(join_given_server)
+        .add_system(connect_to_server)
         .run();
 }
 
-fn game_setup(
+/// Startup system joining the server given on the command line, if any.
+/// Servers which cannot be resolved are left to be chosen on the join screen instead.
+fn join_given_server(
+    options: Res<ConnectionOptions>,
+    mut join_events: ResMut<Events<JoinServerEvent>>,
+) {
+    let server = match &options.server {
+        Some(server) => server,
+        None => return,
+    };
+    match server.to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => {
+            info!("Joining server {}", addr);
+            join_events.send(JoinServerEvent { addr });
+        }
+        Ok(None) => error!("Server {} did not resolve to any address", server),
+        Err(e) => error!("Failed to resolve server {}: {}", server, e),
+    }
+}
+
+#[derive(Default)]
+struct ConnectState {
+    join_server_event_reader: EventReader<JoinServerEvent>,
+}
+
+/// System connecting to the server chosen on the join screen.
+fn connect_to_server(
+    mut state: Local<ConnectState>,
+    join_server_events: Res<Events<JoinServerEvent>>,
+    options: Res<ConnectionOptions>,
+    mut network: ResMut<Network>,
+    mut screen: ResMut<ClientScreen>,
+    mut lobby_view: ResMut<LobbyView>,
+) {
+    for event in state.join_server_event_reader.iter(&join_server_events) {
+        // UDP datagrams are conditioned themselves, so that their reliability layer is exercised
+        let conditions = options.conditions.clone();
+        let backend: io::Result<Box<dyn NetworkBackend>> = match (options.udp, conditions) {
+            (true, Some(conditions)) => UdpBackend::connect(event.addr)
+                .map(|b| Box::new(b.with_conditions(conditions)) as _),
+            (true, None) => UdpBackend::connect(event.addr).map(|b| Box::new(b) as _),
+            (false, Some(conditions)) => TcpBackend::connect(event.addr)
+                .map(|b| Box::new(ConditionedBackend::new(b, conditions)) as _),
+            (false, None) => TcpBackend::connect(event.addr).map(|b| Box::new(b) as _),
+        };
+        let backend = match backend {
+            Ok(backend) => backend,
+            Err(e) => {
+                error!("Failed to connect to {}: {}", event.addr, e);
+                continue;
+            }
+        };
+        network.connect(backend);
+        lobby_view.reset();
+        *screen = ClientScreen::Lobby;
+    }
+}
+
+fn setup(
     commands: &mut Commands,
     asset_server: Res<AssetServer>,
     mut texture_atlases: ResMut<Assets<TextureAtlas>>,
 ) {
     let texture_handle = asset_server.load("sprites/sprite_sheet.png");
     let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(256.0, 256.0), 4, 4);
-    let texture_atlas_handle = texture_atlases.set("SPRITE_SHEET", texture_atlas);
+    texture_atlases.set("SPRITE_SHEET", texture_atlas);
     commands
         .spawn(Camera2dBundle::default())
-        .spawn(UiCameraBundle::default())
+        .spawn(UiCameraBundle::default());
+}
+
+#[derive(Default)]
+struct GameSetupState {
+    match_started_event_reader: EventReader<MatchStartedEvent>,
+}
+
+/// System spawning the game world once the match has started.
+fn game_setup(
+    commands: &mut Commands,
+    mut state: Local<GameSetupState>,
+    match_started_events: Res<Events<MatchStartedEvent>>,
+    asset_server: Res<AssetServer>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    players: Res<Players>,
+    balance: Res<Balance>,
+    mut camera_goal: ResMut<CameraGoal>,
+    mut camera_query: Query<(&Camera, Mut<Transform>)>,
+) {
+    if state.match_started_event_reader.iter(&match_started_events).next().is_none() {
+        return;
+    }
+
+    let font = asset_server.load("fonts/Nunito-Regular.ttf");
+    commands
         .spawn(TextBundle {
             style: Style {
-                align_self: AlignSelf::FlexStart,
+                align_self: AlignSelf::FlexEnd,
                 ..Default::default()
             },
             text: Text {
-                value: "0, 0".to_string(),
-                font: asset_server.load("fonts/Nunito-Regular.ttf"),
+                value: "".to_string(),
+                font: font.clone(),
                 style: TextStyle {
-                    font_size: 60.0,
+                    font_size: 20.0,
                     color: Color::WHITE,
                     alignment: TextAlignment::default(),
                 },
             },
             ..Default::default()
         })
-        .with(ResourcesText)
-        // Planet 1
-        .spawn(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(0),
-            texture_atlas: texture_atlas_handle.clone(),
-            transform: Transform::from_scale(Vec3::splat(1.0)),
+        .with(NetworkStatsText)
+        .spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::Center,
+                ..Default::default()
+            },
+            text: Text {
+                value: "".to_string(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 80.0,
+                    color: players.color(players.local),
+                    alignment: TextAlignment::default(),
+                },
+            },
             ..Default::default()
         })
-        .with(Planet::default())
-        .with_children(|parent| {
-            parent
-                // Moon 1
-                .spawn(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(1),
-                    texture_atlas: texture_atlas_handle.clone(),
-                    transform: Transform::from_scale(Vec3::splat(0.5)),
-                    ..Default::default()
-                })
-                .with(Moon {
-                    orbit_radius: 300.0,
-                    speed: 1.0,
-                    building: None,
-                })
-                // Moon 2
-                .spawn(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(1),
-                    texture_atlas: texture_atlas_handle.clone(),
-                    transform: Transform::from_scale(Vec3::splat(0.5)),
-                    ..Default::default()
-                })
-                .with(Moon {
-                    orbit_radius: 500.0,
-                    speed: 0.5,
-                    building: None,
-                });
-        })
-        // Planet 2
-        .spawn(SpriteSheetBundle {
-            sprite: TextureAtlasSprite::new(0),
-            texture_atlas: texture_atlas_handle.clone(),
-            transform: Transform::from_translation(Vec3::splat(700.0)),
+        .with(MatchOutcomeText)
+        .spawn(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexStart,
+                ..Default::default()
+            },
+            text: Text {
+                value: "".to_string(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: 30.0,
+                    color: Color::WHITE,
+                    alignment: TextAlignment::default(),
+                },
+            },
             ..Default::default()
         })
-        .with(Planet::default())
-        .with_children(|parent| {
-            parent
-                // Moon 1
-                .spawn(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(1),
-                    texture_atlas: texture_atlas_handle.clone(),
-                    transform: Transform::from_scale(Vec3::splat(0.5)),
-                    ..Default::default()
-                })
-                .with(Moon {
-                    orbit_radius: 300.0,
-                    speed: 1.0,
-                    building: None,
-                })
-                // Moon 2
-                .spawn(SpriteSheetBundle {
-                    sprite: TextureAtlasSprite::new(1),
-                    texture_atlas: texture_atlas_handle.clone(),
-                    transform: Transform::from_scale(Vec3::splat(0.5)),
-                    ..Default::default()
-                })
-                .with(Moon {
-                    orbit_radius: 500.0,
-                    speed: 0.5,
-                    building: None,
-                });
-        });
-}
+        .with(TimeControlText);
 
-fn camera_motion(
-    time: Res<Time>,
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&Camera, Mut<Transform>)>,
-) {
-    for (camera, mut trans) in query.iter_mut() {
-        if camera.name == Some(UI_CAMERA.to_string()) {
-            continue;
-        }
-
-        // determine direction based on keyboard input
-        let mut direction = Vec3::splat(0.0);
-        if keyboard_input.pressed(KeyCode::Up) {
-            direction += Vec3::new(0.0, 1.0, 0.0)
-        }
-        if keyboard_input.pressed(KeyCode::Down) {
-            direction += Vec3::new(0.0, -1.0, 0.0)
-        }
-        if keyboard_input.pressed(KeyCode::Left) {
-            direction += Vec3::new(-1.0, 0.0, 0.0)
-        }
-        if keyboard_input.pressed(KeyCode::Right) {
-            direction += Vec3::new(1.0, 0.0, 0.0)
-        }
+    let atlas = texture_atlases.get_handle("SPRITE_SHEET");
+    spawn_map(commands, atlas, &players, &balance);
 
-        // move the camera at constant speed in determined direction
-        let camera_speed = 500.0;
-        let ds = camera_speed * time.delta_seconds;
-        if direction.length() > 0.0 {
-            trans.translation += direction.normalize() * ds;
+    // start out looking at the home planet
+    let home = planet_positions(players.len())[players.local as usize];
+    camera_goal.position = home;
+    for (camera, mut trans) in camera_query.iter_mut() {
+        if camera.name != Some(UI_CAMERA.to_string()) {
+            trans.translation = home.extend(trans.translation.z);
         }
     }
 }
 
-fn kepler_motion(time: Res<Time>, mut query: Query<(&Moon, Mut<Transform>)>) {
-    for (moon, mut trans) in query.iter_mut() {
-        let ds = moon.speed * time.seconds_since_startup;
-        let x = moon.orbit_radius * ds.cos() as f32;
-        let y = moon.orbit_radius * ds.sin() as f32;
-        trans.translation = Vec3::new(x, y, 0.0);
+struct NetworkStatsText;
+
+fn network_stats_text(
+    stats: Res<ConnectionStats>,
+    mut text_query: Query<(&mut Text, &NetworkStatsText)>,
+) {
+    for (mut text, _) in text_query.iter_mut() {
+        text.value = format!(
+            "ping {:.0}ms, jitter {:.0}ms",
+            stats.rtt * 1000.0,
+            stats.jitter * 1000.0
+        );
     }
 }
 
-struct ResourceMiningState {
-    timer: Timer,
+/// System letting the player pause and resume the match and change the game speed.
+fn time_controls(
+    actions: Res<InputActions>,
+    sim_time: Res<NetworkSimulationTime>,
+    time_control: Res<TimeControl>,
+    players: Res<Players>,
+    mut transport: ResMut<Transport>,
+    mut pending: ResMut<PendingActions>,
+) {
+    let speed_index = GAME_SPEEDS.iter().position(|&s| s == sim_time.speed()).unwrap_or(1);
+    let action = if actions.just_pressed(InputAction::TogglePause) {
+        if sim_time.is_paused() {
+            PlayerAction::Resume
+        } else if time_control.pauses_left(players.local) > 0 {
+            PlayerAction::Pause
+        } else {
+            return;
+        }
+    } else if actions.just_pressed(InputAction::SlowDown) && speed_index > 0 {
+        PlayerAction::SetGameSpeed {
+            speed: GAME_SPEEDS[speed_index - 1],
+        }
+    } else if actions.just_pressed(InputAction::SpeedUp) && speed_index + 1 < GAME_SPEEDS.len() {
+        PlayerAction::SetGameSpeed {
+            speed: GAME_SPEEDS[speed_index + 1],
+        }
+    } else {
+        return;
+    };
+    pending.submit(action, sim_time.frame_number(), &mut transport);
 }
 
-impl Default for ResourceMiningState {
-    fn default() -> Self {
-        Self {
-            timer: Timer::from_seconds(1.0, true),
+struct TimeControlText;
+
+/// System showing whether the match is paused, the game speed and the pauses left.
+fn time_control_text(
+    sim_time: Res<NetworkSimulationTime>,
+    time_control: Res<TimeControl>,
+    players: Res<Players>,
+    mut text_query: Query<(&mut Text, &TimeControlText)>,
+) {
+    let pauses_left = time_control.pauses_left(players.local);
+    let value = match time_control.paused_by() {
+        Some(player) => {
+            let name = players.get(player).map_or("Someone", |p| p.name.as_str());
+            format!("PAUSED by {} - press Space to resume", name)
         }
+        None => format!(
+            "Speed {}x - {} of {} pauses left",
+            sim_time.speed(),
+            pauses_left,
+            MAX_PAUSES
+        ),
+    };
+    for (mut text, _) in text_query.iter_mut() {
+        text.value = value.clone();
     }
 }
 
-fn resource_mining(
-    mut state: Local<ResourceMiningState>,
-    time: Res<Time>,
-    mut resources: ResMut<PlayerResources>,
-    moon_query: Query<&Moon>,
-    mut text_query: Query<(&mut Text, &ResourcesText)>,
+struct MatchOutcomeText;
+
+/// System announcing the end of the match, or the elimination of the local player.
+fn match_outcome_text(
+    players: Res<Players>,
+    mut text_query: Query<(&mut Text, &MatchOutcomeText)>,
 ) {
-    if state.timer.tick(time.delta_seconds).just_finished() {
-        for moon in moon_query.iter() {
-            if let Some(BuildingType::Mining) = moon.building {
-                resources.pink += 1;
-            }
+    let value = if players.is_match_over() {
+        match (players.winning_team(), players.get(players.local)) {
+            (Some(team), Some(local)) if team == local.team => "Victory!",
+            (Some(_), _) => "Defeat",
+            (None, _) => "Draw",
         }
-    }
-
+    } else if players.is_eliminated(players.local) {
+        "Eliminated"
+    } else {
+        ""
+    };
     for (mut text, _) in text_query.iter_mut() {
-        text.value = format!("{}, {}", resources.pink, resources.green);
+        text.value = value.to_string();
     }
 }
 
 #[derive(Default)]
 pub struct PlanetAuraState {
-    keyboard_event_reader: EventReader<KeyboardInput>,
-    current_planet: Option<Entity>,
+    command_event_reader: EventReader<GameCommand>,
 }
 
+/// System changing the aura of the selected own planet.
 pub fn planet_auras(
     mut state: Local<PlanetAuraState>,
-    cursor_in_world: Res<CursorInWorld>,
-    keyboard_inputs: Res<Events<KeyboardInput>>,
-    mouse_input: Res<Input<MouseButton>>,
-    mut resources: ResMut<PlayerResources>,
+    actions: Res<InputActions>,
+    command_events: Res<Events<GameCommand>>,
+    selection: Res<Selection>,
+    sim_time: Res<NetworkSimulationTime>,
     mut transport: ResMut<Transport>,
-    mut planet_query: Query<(Entity, Mut<Planet>, &GlobalTransform)>,
+    mut pending: ResMut<PendingActions>,
+    players: Res<Players>,
+    planet_query: Query<(&BodyId, &Owner, &Planet)>,
 ) {
-    let world_coords = cursor_in_world.position;
-
-    // change to building mode on button press
-    for event in state.keyboard_event_reader.iter(&keyboard_inputs) {
-        if let Some(entity) = state.current_planet {
-            if event.state == ElementState::Pressed {
-                let (_, mut planet, _) = planet_query.get_mut(entity).unwrap();
-                planet.current_aura = match event.key_code {
-                    Some(KeyCode::P) => Some(Aura::ProductionSpeed),
-                    Some(KeyCode::R) => Some(Aura::RocketSpeed),
-                    Some(KeyCode::D) => Some(Aura::RocketDamage),
-                    Some(KeyCode::M) => Some(Aura::MoonSpeed),
-                    Some(KeyCode::S) => Some(Aura::Shield),
-                    _ => planet.current_aura,
-                };
-                let aura_change = PlayerAction::ChangeAura {
-                    aura: planet.current_aura,
-                    planet: entity.id(),
-                };
-                let serialized = bincode::serialize(&aura_change).unwrap();
-                transport.send(serialized);
-                //resources.pink -= building_cost(building);
-                state.current_planet = None;
-            }
+    let mut requested = [
+        (InputAction::AuraProductionSpeed, Aura::ProductionSpeed),
+        (InputAction::AuraRocketSpeed, Aura::RocketSpeed),
+        (InputAction::AuraRocketDamage, Aura::RocketDamage),
+        (InputAction::AuraMoonSpeed, Aura::MoonSpeed),
+        (InputAction::AuraShield, Aura::Shield),
+    ]
+    .iter()
+    .find(|(action, _)| actions.just_pressed(*action))
+    .map(|&(_, aura)| aura);
+    for command in state.command_event_reader.iter(&command_events) {
+        if let GameCommand::ChangeAura(aura) = *command {
+            requested = Some(aura);
         }
     }
 
-    if mouse_input.pressed(MouseButton::Left) {
-        // check if cursor is inside of a moon
-        // TODO: use actual sprite size instead of magic number
-        for (entity, _, trans) in planet_query.iter_mut() {
-            if trans.translation.x - 128.0 * trans.scale.x <= world_coords.x
-                && trans.translation.x + 128.0 * trans.scale.x >= world_coords.x
-                && trans.translation.y - 128.0 * trans.scale.y <= world_coords.y
-                && trans.translation.y + 128.0 * trans.scale.y >= world_coords.y
-                //&& resources.pink >= building_cost(building)
-            {
-                state.current_planet = Some(entity);
-            }
+    let aura = match requested {
+        Some(aura) => aura,
+        None => return,
+    };
+    // the aura changes once the action is executed on its frame, like on all other clients
+    let selected = selection.selected().and_then(|entity| planet_query.get(entity).ok());
+    if let Some((id, owner, _)) = selected {
+        if owner.0 == players.local {
+            let aura_change = PlayerAction::ChangeAura {
+                aura: Some(aura),
+                planet: id.0,
+            };
+            pending.submit(aura_change, sim_time.frame_number(), &mut transport);
         }
     }
 }
@@ -1,73 +1,667 @@
 // Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
-use std::io::Write;
-use std::net::{TcpListener, TcpStream};
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{mpsc, Mutex},
+    thread,
+    time::Duration,
+};
 
-use bevy::{prelude::*, log::LogPlugin};
+use bevy::{
+    app::{AppExit, ScheduleRunnerSettings},
+    core::DefaultTaskPoolOptions,
+    prelude::*,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+};
+use tracing_subscriber::EnvFilter;
 
-use moonshot::network::{ServerTurn, Transport};
+use moonshot::balance::{Balance, BALANCE_FILE};
+use moonshot::network::{
+    ClientMessage, ConditionedBackend, Delivery, DiscoveryPacket, GameId, GameInfo, GameSettings,
+    LobbyPlayer, LobbyRequest, LobbyResponse, MatchTeams, NetworkBackend, NetworkConditions,
+    NetworkPlugin, NetworkSimulationEvent, ServerAnnouncement, ServerConnections, ServerMessage,
+    TeamMode, UdpBackend, DISCOVERY_PORT, MAPS, MAX_PLAYERS, MIN_PLAYERS, PROTOCOL_VERSION,
+};
+
+const LISTEN_ADDR: &str = "0.0.0.0:7777";
+const DEFAULT_SERVER_NAME: &str = "Moonshot Server";
+
+/// Seconds a match waits for its players to connect before it is given up on.
+const MATCH_CONNECT_TIMEOUT: f64 = 60.0;
+
+/// Time between polls of the UDP socket for new datagrams.
+const UDP_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Sends payloads to a connected player with the given delivery guarantee.
+type Outgoing = UnboundedSender<(Vec<u8>, Delivery)>;
 
 fn main() {
-    info!("Starting listening socket");
-    let mut players = Vec::new();
-    let mut listener = TcpListener::bind("127.0.0.1:7777").unwrap();
-    info!("Started listening on {:?}, waiting for players...", listener.local_addr());
-    handle_connects(&mut listener, &mut players, 2);
-    info!("Found 2 players!");
+    // matches run without the log plugin, so logging is set up once for the whole server
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt().with_env_filter(filter).init();
 
+    let conditions = match NetworkConditions::from_args(std::env::args()) {
+        Ok(conditions) => conditions,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let udp = std::env::args().any(|arg| arg == "--udp");
+
+    // the name shown to players discovering the server in their local network
+    let name = std::env::args()
+        .skip_while(|arg| arg != "--name")
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_SERVER_NAME.to_string());
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    if let Err(e) = runtime.block_on(run_server(name, conditions, udp)) {
+        error!("Server stopped: {}", e);
+    }
+}
+
+fn condition(
+    backend: Box<dyn NetworkBackend>,
+    conditions: Option<NetworkConditions>,
+) -> Box<dyn NetworkBackend> {
+    match conditions {
+        Some(conditions) => Box::new(ConditionedBackend::new(backend, conditions)),
+        None => backend,
+    }
+}
+
+/// Runs a single match until it is decided or all of its players left.
+/// Blocks the current thread, so matches are usually run on a thread of their own.
+fn run_match(
+    backend: Box<dyn NetworkBackend>,
+    teams: MatchTeams,
+    on_exit: Option<Box<dyn FnOnce() + Send>>,
+) {
     App::build()
-        .add_resource(players)
+        .add_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(1.0 / 60.0)))
+        .add_resource(DefaultTaskPoolOptions::with_num_threads(1))
         .add_plugins(MinimalPlugins)
-        .add_plugin(LogPlugin)
-        .add_plugin(ServerPlugin)
+        .add_plugin(NetworkPlugin::server(backend))
+        .add_resource(teams)
+        .add_system(end_match)
         .run();
+
+    if let Some(on_exit) = on_exit {
+        on_exit();
+    }
+}
+
+/// System ending the match once it is decided, all players have left or none ever connected.
+fn end_match(
+    mut had_players: Local<bool>,
+    time: Res<Time>,
+    connections: Res<ServerConnections>,
+    mut app_exit_events: ResMut<Events<AppExit>>,
+) {
+    if connections.is_match_over() {
+        info!("Match is decided, ending match");
+        app_exit_events.send(AppExit);
+    } else if !connections.stats.is_empty() {
+        *had_players = true;
+    } else if *had_players {
+        info!("All players left, ending match");
+        app_exit_events.send(AppExit);
+    } else if time.seconds_since_startup > MATCH_CONNECT_TIMEOUT {
+        info!("No player connected within {}s, ending match", MATCH_CONNECT_TIMEOUT);
+        app_exit_events.send(AppExit);
+    }
 }
 
-struct ServerPlugin;
+/// Accepts players over TCP, or over UDP if `udp` is set, who then meet in the lobby.
+async fn run_server(
+    name: String,
+    conditions: Option<NetworkConditions>,
+    udp: bool,
+) -> io::Result<()> {
+    if udp {
+        let mut backend = UdpBackend::listen(LISTEN_ADDR)?;
+        let local_addr = backend.local_addr()?;
+        info!("Started listening for UDP on {:?}", local_addr);
+        // the conditions are simulated on the datagrams, below the reliability layer
+        if let Some(conditions) = conditions {
+            backend = backend.with_conditions(conditions);
+        }
+        let lobby_sender = start_lobby(name, local_addr.port(), None).await?;
+        relay_udp(backend, lobby_sender).await;
+        return Ok(());
+    }
+
+    let listener = TcpListener::bind(LISTEN_ADDR).await?;
+    let local_addr = listener.local_addr()?;
+    info!("Started listening on {:?}", local_addr);
+    let lobby_sender = start_lobby(name, local_addr.port(), conditions).await?;
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        info!("Accepted a new connection from {}", addr);
+        tokio::spawn(handle_connection(stream, addr, lobby_sender.clone()));
+    }
+}
 
-impl Plugin for ServerPlugin {
-    fn build(&self, app: &mut AppBuilder) {
-        app.add_resource(Transport::default())
-            //.add_resource(Events::<NetworkSimulationEvent>::default())
-            //.add_resource(NetworkSimulationTime::default())
-            //.add_system(update_simulation_time)
-            .add_system(send_messages)
-            .add_system(handle_messages);
+/// Spawns the lobby and answers discovery queries for it, returning its event sender.
+async fn start_lobby(
+    name: String,
+    port: u16,
+    conditions: Option<NetworkConditions>,
+) -> io::Result<UnboundedSender<LobbyEvent>> {
+    let (lobby_sender, lobby_receiver) = unbounded_channel();
+    let lobby = Lobby::new(lobby_sender.clone(), name, port, conditions);
+    tokio::spawn(lobby.run(lobby_receiver));
+
+    let discovery_socket = UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT)).await?;
+    tokio::spawn(answer_discovery_queries(discovery_socket, lobby_sender.clone()));
+    Ok(lobby_sender)
+}
+
+/// Passes the messages of all players connected over UDP between the socket and the lobby.
+///
+/// Returns once the lobby has stopped.
+async fn relay_udp(mut backend: UdpBackend, lobby: UnboundedSender<LobbyEvent>) {
+    let (outgoing_sender, outgoing) = mpsc::channel();
+    loop {
+        for event in backend.poll() {
+            let event = match event {
+                NetworkSimulationEvent::Connect(addr) => {
+                    info!("Accepted a new connection from {}", addr);
+                    let (sender, mut receiver) = unbounded_channel();
+                    let outgoing_sender = outgoing_sender.clone();
+                    tokio::spawn(async move {
+                        while let Some((payload, delivery)) = receiver.recv().await {
+                            if outgoing_sender.send((addr, payload, delivery)).is_err() {
+                                break;
+                            }
+                        }
+                    });
+                    LobbyEvent::Connected(addr, sender)
+                }
+                NetworkSimulationEvent::Message(addr, payload) => {
+                    LobbyEvent::Message(addr, payload)
+                }
+                NetworkSimulationEvent::Disconnect(addr) => LobbyEvent::Disconnected(addr),
+            };
+            if lobby.send(event).is_err() {
+                return;
+            }
+        }
+        for (addr, payload, delivery) in outgoing.try_iter() {
+            if let Err(e) = backend.send(addr, &payload, delivery) {
+                warn!("Failed to send to {}: {}", addr, e);
+            }
+        }
+        tokio::time::sleep(UDP_POLL_INTERVAL).await;
     }
 }
 
-fn send_messages(mut transport: ResMut<Transport>, mut streams: ResMut<Vec<TcpStream>>) {
-    let messages = transport.drain_messages();
-    for message in messages {
-        for stream in streams.iter_mut() {
-            if let Err(e) = stream.write_all(&message.payload) {
-                error!("Failed to send network message: {}", e);
+/// Passes length-prefixed messages between the client's TCP stream and the lobby.
+async fn handle_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    lobby: UnboundedSender<LobbyEvent>,
+) {
+    if let Err(e) = stream.set_nodelay(true) {
+        warn!("Failed to disable Nagle's algorithm for {}: {}", addr, e);
+    }
+    let (mut reader, mut writer) = stream.into_split();
+    let (outgoing_sender, mut outgoing) = unbounded_channel();
+    let _ = lobby.send(LobbyEvent::Connected(addr, outgoing_sender));
+
+    tokio::spawn(async move {
+        // the stream is reliable anyway
+        while let Some((payload, _)) = outgoing.recv().await {
+            let result = async {
+                writer.write_u16(payload.len() as u16).await?;
+                writer.write_all(&payload).await
+            };
+            if let Err(e) = result.await {
+                warn!("Failed to send to {}: {}", addr, e);
+                break;
             }
         }
+    });
+
+    loop {
+        let length = match reader.read_u16().await {
+            Ok(length) => length,
+            Err(_) => break,
+        };
+        let mut payload = vec![0; length as usize];
+        if reader.read_exact(&mut payload).await.is_err() {
+            break;
+        }
+        let _ = lobby.send(LobbyEvent::Message(addr, payload));
     }
+    let _ = lobby.send(LobbyEvent::Disconnected(addr));
 }
 
-fn handle_messages(mut streams: ResMut<Vec<TcpStream>>, mut transport: ResMut<Transport>) {
-    for stream in streams.iter_mut() {
-        if let Ok(action) = bincode::deserialize_from(&mut *stream) {
-            trace!("Received from client: {:?}", action);
-            let msg = ServerTurn::new(vec![action]);
-            let serialized = bincode::serialize(&msg).unwrap();
-            transport.send(serialized);
+/// Answers discovery queries broadcast by clients looking for servers in the local network.
+async fn answer_discovery_queries(socket: UdpSocket, lobby: UnboundedSender<LobbyEvent>) {
+    let mut buffer = [0; 1024];
+    loop {
+        let (length, source) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Failed to receive discovery query: {}", e);
+                continue;
+            }
+        };
+        match bincode::deserialize(&buffer[..length]) {
+            Ok(DiscoveryPacket::Query) => {}
+            Ok(DiscoveryPacket::Announcement(_)) => continue,
+            Err(e) => {
+                debug!("Received malformed discovery packet from {}: {}", source, e);
+                continue;
+            }
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        if lobby.send(LobbyEvent::Announce(sender)).is_err() {
+            break;
+        }
+        let announcement = match receiver.await {
+            Ok(announcement) => announcement,
+            Err(_) => break,
+        };
+        let serialized = bincode::serialize(&DiscoveryPacket::Announcement(announcement)).unwrap();
+        if let Err(e) = socket.send_to(&serialized, source).await {
+            warn!("Failed to answer discovery query from {}: {}", source, e);
         }
     }
 }
 
-fn handle_connects(listener: &mut TcpListener, streams: &mut Vec<TcpStream>, max_conns: usize) {
-    for conn in listener.incoming() {
-        if let Ok(stream) = conn {
-            info!("Accepted a new connection");
-            stream.set_nonblocking(true).unwrap();
-            streams.push(stream);
-            if streams.len() >= max_conns {
+enum LobbyEvent {
+    Connected(SocketAddr, Outgoing),
+    Message(SocketAddr, Vec<u8>),
+    Disconnected(SocketAddr),
+    MatchEnded(GameId),
+    /// Asks for a description of the server to answer a discovery query with.
+    Announce(oneshot::Sender<ServerAnnouncement>),
+}
+
+/// A connected player which is not necessarily in a game.
+struct LobbyClient {
+    name: String,
+    sender: Outgoing,
+    game: Option<GameId>,
+}
+
+/// A game which players can join, with the addresses of its players in the same order as in
+/// `info.players`.
+struct OpenGame {
+    info: GameInfo,
+    addrs: Vec<SocketAddr>,
+}
+
+struct MatchHandle {
+    players: Vec<SocketAddr>,
+    events: mpsc::Sender<NetworkSimulationEvent>,
+}
+
+/// Keeps track of all connected players and open games, starts matches once all players of a
+/// game are ready and routes the messages of players in a match to it.
+struct Lobby {
+    events: UnboundedSender<LobbyEvent>,
+    name: String,
+    /// Port players connect to the server on
+    port: u16,
+    conditions: Option<NetworkConditions>,
+    clients: HashMap<SocketAddr, LobbyClient>,
+    games: HashMap<GameId, OpenGame>,
+    matches: HashMap<GameId, MatchHandle>,
+    player_matches: HashMap<SocketAddr, GameId>,
+    next_game_id: GameId,
+    next_player_number: u32,
+}
+
+impl Lobby {
+    fn new(
+        events: UnboundedSender<LobbyEvent>,
+        name: String,
+        port: u16,
+        conditions: Option<NetworkConditions>,
+    ) -> Self {
+        Self {
+            events,
+            name,
+            port,
+            conditions,
+            clients: HashMap::new(),
+            games: HashMap::new(),
+            matches: HashMap::new(),
+            player_matches: HashMap::new(),
+            next_game_id: 0,
+            next_player_number: 1,
+        }
+    }
+
+    async fn run(mut self, mut receiver: UnboundedReceiver<LobbyEvent>) {
+        while let Some(event) = receiver.recv().await {
+            self.handle(event);
+        }
+    }
+
+    fn handle(&mut self, event: LobbyEvent) {
+        match event {
+            LobbyEvent::Connected(addr, sender) => {
+                let client = LobbyClient {
+                    name: format!("Player {}", self.next_player_number),
+                    sender,
+                    game: None,
+                };
+                self.next_player_number += 1;
+                self.clients.insert(addr, client);
+            }
+            LobbyEvent::Message(addr, payload) => {
+                if let Some(handle) = self.player_match(addr) {
+                    let _ = handle.events.send(NetworkSimulationEvent::Message(addr, payload));
+                    return;
+                }
+                match bincode::deserialize::<ClientMessage>(&payload) {
+                    Ok(ClientMessage::Lobby(request)) => self.handle_request(addr, request),
+                    Ok(message) => trace!("Ignoring message from {} in lobby: {:?}", addr, message),
+                    Err(e) => warn!("Received malformed message from {}: {}", addr, e),
+                }
+            }
+            LobbyEvent::Disconnected(addr) => {
+                info!("Player {} disconnected", addr);
+                if let Some(handle) = self.player_match(addr) {
+                    let _ = handle.events.send(NetworkSimulationEvent::Disconnect(addr));
+                }
+                self.leave_game(addr);
+                self.clients.remove(&addr);
+                self.player_matches.remove(&addr);
+            }
+            LobbyEvent::MatchEnded(id) => {
+                info!("Match {} ended", id);
+                if let Some(handle) = self.matches.remove(&id) {
+                    for addr in handle.players {
+                        self.player_matches.remove(&addr);
+                        if let Some(client) = self.clients.get_mut(&addr) {
+                            client.game = None;
+                        }
+                    }
+                }
+            }
+            LobbyEvent::Announce(sender) => {
+                let _ = sender.send(self.announcement());
+            }
+        }
+    }
+
+    fn announcement(&self) -> ServerAnnouncement {
+        let fullest_game = self.games.values().max_by_key(|game| game.info.players.len());
+        ServerAnnouncement {
+            name: self.name.clone(),
+            map: fullest_game.map_or(MAPS[0], |game| game.info.settings.map.as_str()).to_string(),
+            players: self.clients.len() as u32,
+            open_games: self.games.len() as u32,
+            protocol_version: PROTOCOL_VERSION,
+            port: self.port,
+        }
+    }
+
+    fn handle_request(&mut self, addr: SocketAddr, request: LobbyRequest) {
+        debug!("Lobby request from {}: {:?}", addr, request);
+        match request {
+            LobbyRequest::ListGames => {
+                let games = self.games.values().map(|game| game.info.clone()).collect();
+                self.send(addr, LobbyResponse::GameList(games));
+            }
+            LobbyRequest::CreateGame(settings) => {
+                if let Err(reason) = validate_settings(&settings) {
+                    self.send(addr, LobbyResponse::Error(reason));
+                    return;
+                }
+                let id = self.next_game_id;
+                self.next_game_id += 1;
+                let info = GameInfo {
+                    id,
+                    settings,
+                    players: Vec::new(),
+                };
+                self.games.insert(
+                    id,
+                    OpenGame {
+                        info,
+                        addrs: Vec::new(),
+                    },
+                );
+                self.join_game(addr, id);
+            }
+            LobbyRequest::JoinGame(id) => self.join_game(addr, id),
+            LobbyRequest::SetReady(ready) => {
+                let id = match self.current_game(addr) {
+                    Some(id) => id,
+                    None => return,
+                };
+                let game = self.games.get_mut(&id).unwrap();
+                let index = game.addrs.iter().position(|&a| a == addr).unwrap();
+                game.info.players[index].ready = ready;
+
+                if game.info.can_start() {
+                    self.start_match(id);
+                } else {
+                    self.broadcast_game(id);
+                }
+            }
+            LobbyRequest::SetTeam(team) => {
+                let id = match self.current_game(addr) {
+                    Some(id) => id,
+                    None => return,
+                };
+                let game = self.games.get_mut(&id).unwrap();
+                match game.info.settings.teams {
+                    TeamMode::Teams(count) if team < count => {}
+                    _ => {
+                        self.send(addr, LobbyResponse::Error("No such team".to_string()));
+                        return;
+                    }
+                }
+                let index = game.addrs.iter().position(|&a| a == addr).unwrap();
+                game.info.players[index].team = team;
+                self.broadcast_game(id);
+            }
+            LobbyRequest::LeaveGame => {
+                self.leave_game(addr);
+                self.send(addr, LobbyResponse::LeftGame);
+            }
+        }
+    }
+
+    /// Returns the game the player is in, telling them about the error if there is none.
+    fn current_game(&self, addr: SocketAddr) -> Option<GameId> {
+        let id = self.clients.get(&addr).and_then(|client| client.game);
+        if id.is_none() {
+            self.send(addr, LobbyResponse::Error("Not in a game".to_string()));
+        }
+        id
+    }
+
+    fn join_game(&mut self, addr: SocketAddr, id: GameId) {
+        match self.games.get(&id) {
+            Some(game) if game.info.is_full() => {
+                self.send(addr, LobbyResponse::Error("Game is full".to_string()));
                 return;
             }
+            None => {
+                self.send(addr, LobbyResponse::Error("Game does not exist".to_string()));
+                return;
+            }
+            Some(_) => {}
+        }
+
+        self.leave_game(addr);
+        let client = self.clients.get_mut(&addr).unwrap();
+        client.game = Some(id);
+        let game = self.games.get_mut(&id).unwrap();
+        let player = LobbyPlayer {
+            name: client.name.clone(),
+            ready: false,
+            team: game.info.smallest_team(),
+        };
+        game.info.players.push(player);
+        game.addrs.push(addr);
+        self.broadcast_game(id);
+    }
+
+    fn leave_game(&mut self, addr: SocketAddr) {
+        let id = match self.clients.get_mut(&addr).and_then(|client| client.game.take()) {
+            Some(id) => id,
+            None => return,
+        };
+        let game = match self.games.get_mut(&id) {
+            Some(game) => game,
+            // the game has already turned into a match
+            None => return,
+        };
+        if let Some(index) = game.addrs.iter().position(|&a| a == addr) {
+            game.addrs.remove(index);
+            game.info.players.remove(index);
+        }
+
+        if game.addrs.is_empty() {
+            self.games.remove(&id);
+        } else {
+            self.broadcast_game(id);
+        }
+    }
+
+    fn send(&self, addr: SocketAddr, response: LobbyResponse) {
+        if let Some(client) = self.clients.get(&addr) {
+            let serialized = bincode::serialize(&ServerMessage::Lobby(response)).unwrap();
+            let _ = client.sender.send((serialized, Delivery::Reliable));
+        }
+    }
+
+    /// Sends the current state of the game to all of its players.
+    fn broadcast_game(&self, id: GameId) {
+        let game = &self.games[&id];
+        for &addr in &game.addrs {
+            self.send(addr, LobbyResponse::GameUpdated(game.info.clone()));
+        }
+    }
+
+    fn player_match(&self, addr: SocketAddr) -> Option<&MatchHandle> {
+        self.player_matches.get(&addr).and_then(|id| self.matches.get(id))
+    }
+
+    fn start_match(&mut self, id: GameId) {
+        let game = self.games.remove(&id).unwrap();
+        info!("Starting match {} with players {:?}", id, game.addrs);
+
+        // the balance file is read again for every match, so changes apply without a restart
+        let balance = Balance::load(BALANCE_FILE).unwrap_or_else(|e| {
+            warn!("Playing with the default balance: {}", e);
+            Balance::default()
+        });
+        let balance_hash = balance.hash();
+        info!("Match {} is played with balance {:016x}", id, balance_hash);
+
+        let (event_sender, event_receiver) = mpsc::channel();
+        let mut peers = HashMap::new();
+        for (index, &addr) in game.addrs.iter().enumerate() {
+            let response = LobbyResponse::MatchStarted {
+                game: game.info.clone(),
+                player: index as u8,
+                balance: balance.clone(),
+                balance_hash,
+            };
+            self.send(addr, response);
+            peers.insert(addr, self.clients[&addr].sender.clone());
+            self.player_matches.insert(addr, id);
+            let _ = event_sender.send(NetworkSimulationEvent::Connect(addr));
+        }
+        let backend = MatchBackend {
+            events: Mutex::new(event_receiver),
+            peers,
+        };
+
+        let backend = condition(Box::new(backend), self.conditions.clone());
+        let teams = (0..game.addrs.len()).map(|player| game.info.team(player)).collect();
+        let teams = MatchTeams::new(teams);
+        let lobby_events = self.events.clone();
+        let on_exit = Box::new(move || {
+            let _ = lobby_events.send(LobbyEvent::MatchEnded(id));
+        });
+        thread::Builder::new()
+            .name(format!("match-{}", id))
+            .spawn(move || run_match(backend, teams, Some(on_exit)))
+            .unwrap();
+
+        self.matches.insert(
+            id,
+            MatchHandle {
+                players: game.addrs,
+                events: event_sender,
+            },
+        );
+    }
+}
+
+fn validate_settings(settings: &GameSettings) -> Result<(), String> {
+    if settings.name.trim().is_empty() || settings.name.len() > 32 {
+        return Err("Game name must have between 1 and 32 characters".to_string());
+    }
+    if !MAPS.contains(&settings.map.as_str()) {
+        return Err(format!("Unknown map: {}", settings.map));
+    }
+    if settings.player_count < MIN_PLAYERS || settings.player_count > MAX_PLAYERS {
+        return Err(format!(
+            "Player count must be between {} and {}",
+            MIN_PLAYERS, MAX_PLAYERS
+        ));
+    }
+    if let TeamMode::Teams(count) = settings.teams {
+        if count < 2 || count > settings.player_count {
+            return Err("There must be between 2 teams and one team per player".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Network backend of a single match, exchanging messages with the lobby's connection tasks.
+struct MatchBackend {
+    events: Mutex<mpsc::Receiver<NetworkSimulationEvent>>,
+    peers: HashMap<SocketAddr, Outgoing>,
+}
+
+impl NetworkBackend for MatchBackend {
+    fn send(
+        &mut self,
+        destination: SocketAddr,
+        payload: &[u8],
+        delivery: Delivery,
+    ) -> io::Result<()> {
+        let peer = self.peers.get(&destination).ok_or(io::ErrorKind::NotConnected)?;
+        peer.send((payload.to_vec(), delivery))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn peers(&self) -> Vec<SocketAddr> {
+        self.peers.keys().copied().collect()
+    }
+
+    fn poll(&mut self) -> Vec<NetworkSimulationEvent> {
+        let events: Vec<_> = self.events.lock().unwrap().try_iter().collect();
+        for event in &events {
+            if let NetworkSimulationEvent::Disconnect(addr) = event {
+                self.peers.remove(addr);
+            }
         }
+        events
     }
 }
@@ -0,0 +1,351 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::str::FromStr;
+
+use bevy::prelude::*;
+
+use crate::balance::{Balance, LocalBalanceHash};
+use crate::building::BuildingType;
+use crate::components::{Aura, BodyId, Expense, Moon, Owner, Planet, PlayerResources};
+use crate::map::spawn_map;
+use crate::network::*;
+use crate::players::Players;
+use crate::rng::Rng;
+
+/// Seconds between two requests for the list of open games while looking for one to join.
+const LOBBY_REFRESH_INTERVAL: f32 = 2.0;
+
+/// Auras a bot picks from for its home planet.
+const BOT_AURAS: [Aura; 5] = [
+    Aura::ProductionSpeed,
+    Aura::RocketSpeed,
+    Aura::RocketDamage,
+    Aura::MoonSpeed,
+    Aura::Shield,
+];
+
+/// How well a bot plays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    /// Parses the difficulty from the `--difficulty` argument, defaulting to normal.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        match args.into_iter().skip_while(|arg| arg != "--difficulty").nth(1) {
+            Some(value) => value.parse(),
+            None => Ok(Difficulty::Normal),
+        }
+    }
+
+    /// Seconds of game time the bot waits between two decisions.
+    fn think_interval(self) -> f32 {
+        match self {
+            Difficulty::Easy => 3.0,
+            Difficulty::Normal => 1.5,
+            Difficulty::Hard => 0.5,
+        }
+    }
+
+    /// Largest angle (in radians) by which the bot misses its target when aiming.
+    fn aim_error(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.3,
+            Difficulty::Normal => 0.1,
+            Difficulty::Hard => 0.0,
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Difficulty::Easy),
+            "normal" => Ok(Difficulty::Normal),
+            "hard" => Ok(Difficulty::Hard),
+            _ => Err(format!("Invalid difficulty: {}", s)),
+        }
+    }
+}
+
+/// What a bot spends its crystals on first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Builds a mining moon first and saves up for its buildings before launching rockets.
+    Economy,
+    /// Builds a production moon first and launches rockets whenever it can afford them.
+    Rush,
+}
+
+impl Strategy {
+    /// Parses the strategy from the `--strategy` argument, defaulting to economy.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        match args.into_iter().skip_while(|arg| arg != "--strategy").nth(1) {
+            Some(value) => value.parse(),
+            None => Ok(Strategy::Economy),
+        }
+    }
+
+    /// Order in which the bot builds on its moons.
+    fn build_order(self) -> [BuildingType; 2] {
+        match self {
+            Strategy::Economy => [BuildingType::Mining, BuildingType::Production],
+            Strategy::Rush => [BuildingType::Production, BuildingType::Mining],
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "economy" => Ok(Strategy::Economy),
+            "rush" => Ok(Strategy::Rush),
+            _ => Err(format!("Invalid strategy: {}", s)),
+        }
+    }
+}
+
+/// State of the AI controlling the local player.
+pub struct Bot {
+    pub difficulty: Difficulty,
+    pub strategy: Strategy,
+    /// Whether the bot is playing a match, as opposed to waiting in the lobby
+    pub in_match: bool,
+    rng: Rng,
+    think_timer: Timer,
+}
+
+impl Bot {
+    pub fn new(difficulty: Difficulty, strategy: Strategy, seed: u64) -> Self {
+        Self {
+            difficulty,
+            strategy,
+            in_match: false,
+            rng: Rng::new(seed),
+            think_timer: Timer::from_seconds(difficulty.think_interval(), true),
+        }
+    }
+}
+
+/// This plugin lets an AI play instead of a human, for clients without any graphics.
+///
+/// The bot joins the first open game in the lobby (or creates one), declares to be ready and
+/// then plays the match by issuing actions like every other player.
+pub struct BotPlugin {
+    pub difficulty: Difficulty,
+    pub strategy: Strategy,
+    /// Seed of the random decisions, e.g. to tell multiple bots apart
+    pub seed: u64,
+}
+
+impl Plugin for BotPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // bots without a balance file of their own, e.g. in balance runs, skip the check
+        app.add_resource(Bot::new(self.difficulty, self.strategy, self.seed))
+            .init_resource::<LocalBalanceHash>()
+            .add_system(bot_lobby)
+            .add_system(bot_think);
+    }
+}
+
+fn send_request(transport: &mut Transport, request: LobbyRequest) {
+    let serialized = bincode::serialize(&ClientMessage::Lobby(request)).unwrap();
+    transport.send(serialized);
+}
+
+#[derive(Default)]
+pub struct BotLobbyState {
+    lobby_event_reader: EventReader<LobbyResponse>,
+    refresh_timer: Timer,
+    /// Game the bot has joined or created and is waiting in
+    current_game: Option<GameId>,
+}
+
+/// System looking for a game to play in the lobby and setting up the match once it started.
+pub fn bot_lobby(
+    commands: &mut Commands,
+    mut state: Local<BotLobbyState>,
+    time: Res<Time>,
+    lobby_events: Res<Events<LobbyResponse>>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut bot: ResMut<Bot>,
+    mut players: ResMut<Players>,
+    mut balance: ResMut<Balance>,
+    local_balance_hash: Res<LocalBalanceHash>,
+    mut resources: ResMut<PlayerResources>,
+    mut transport: ResMut<Transport>,
+) {
+    for response in state.lobby_event_reader.iter(&lobby_events) {
+        match response {
+            LobbyResponse::GameList(games) if state.current_game.is_none() => {
+                let request = match games.iter().find(|game| !game.is_full()) {
+                    Some(game) => LobbyRequest::JoinGame(game.id),
+                    None => LobbyRequest::CreateGame(GameSettings {
+                        name: "Bot Game".to_string(),
+                        ..Default::default()
+                    }),
+                };
+                send_request(&mut transport, request);
+            }
+            LobbyResponse::GameUpdated(game) => {
+                // the bot is ready as soon as it joined, waiting for the others to be as well
+                if state.current_game != Some(game.id) {
+                    send_request(&mut transport, LobbyRequest::SetReady(true));
+                }
+                state.current_game = Some(game.id);
+            }
+            LobbyResponse::LeftGame => state.current_game = None,
+            LobbyResponse::Error(reason) => {
+                warn!("Lobby refused request of bot: {}", reason);
+                state.current_game = None;
+            }
+            LobbyResponse::MatchStarted {
+                game,
+                player,
+                balance: match_balance,
+                balance_hash,
+            } => {
+                info!("Bot plays match {} as player {}", game.id, player);
+                local_balance_hash.check(*balance_hash);
+                *players = Players::new(game, *player);
+                *balance = match_balance.clone();
+                *resources = balance.starting_resources();
+                let atlas = texture_atlases.get_handle("SPRITE_SHEET");
+                spawn_map(commands, atlas, &players, &balance);
+                bot.in_match = true;
+            }
+            _ => {}
+        }
+    }
+
+    if bot.in_match || state.current_game.is_some() {
+        return;
+    }
+    // a fresh timer has finished right away, so the list is requested as soon as possible
+    if state.refresh_timer.tick(time.delta_seconds).finished() {
+        state.refresh_timer = Timer::from_seconds(LOBBY_REFRESH_INTERVAL, false);
+        send_request(&mut transport, LobbyRequest::ListGames);
+    }
+}
+
+/// System letting the bot decide on its next actions every once in a while.
+///
+/// The bot builds on its moons in the order given by its strategy and launches rockets from its
+/// production moons at the closest enemy planet.
+pub fn bot_think(
+    mut bot: ResMut<Bot>,
+    time: Res<Time>,
+    sim_time: Res<NetworkSimulationTime>,
+    players: Res<Players>,
+    balance: Res<Balance>,
+    mut resources: ResMut<PlayerResources>,
+    mut transport: ResMut<Transport>,
+    mut pending: ResMut<PendingActions>,
+    moon_query: Query<(&BodyId, &Owner, &Moon, &GlobalTransform)>,
+    planet_query: Query<(&BodyId, &Owner, &Planet, &GlobalTransform)>,
+) {
+    let local = players.local;
+    if !bot.in_match || players.is_eliminated(local) || players.is_match_over() {
+        return;
+    }
+    let delta_seconds = time.delta_seconds * sim_time.time_scale();
+    if !bot.think_timer.tick(delta_seconds).just_finished() {
+        return;
+    }
+
+    // an aura is chosen once, unless the change is still waiting for its frame
+    let home = planet_query.iter().find(|(_, owner, _, _)| owner.0 == local);
+    if let Some((id, _, planet, _)) = home {
+        let aura_pending = pending.iter().any(|p| match p.action {
+            PlayerAction::ChangeAura { planet, .. } => planet == id.0,
+            _ => false,
+        });
+        if planet.current_aura.is_none() && !aura_pending {
+            let index = (bot.rng.next_u64() % BOT_AURAS.len() as u64) as usize;
+            let aura_change = PlayerAction::ChangeAura {
+                aura: Some(BOT_AURAS[index]),
+                planet: id.0,
+            };
+            pending.submit(aura_change, sim_time.frame_number(), &mut transport);
+        }
+    }
+
+    // buildings which are planned but not executed yet count as built already
+    let buildings: Vec<(u32, Option<BuildingType>)> = moon_query
+        .iter()
+        .filter(|(_, owner, _, _)| owner.0 == local)
+        .map(|(id, _, moon, _)| {
+            let planned = pending.iter().find_map(|p| match p.action {
+                PlayerAction::Build { building, moon } if moon == id.0 => Some(building),
+                _ => None,
+            });
+            (id.0, moon.building.or(planned))
+        })
+        .collect();
+    let wanted = bot
+        .strategy
+        .build_order()
+        .iter()
+        .copied()
+        .find(|&wanted| buildings.iter().all(|&(_, b)| b != Some(wanted)));
+    let free_moon = buildings.iter().find(|(_, b)| b.is_none()).map(|&(moon, _)| moon);
+    if let (Some(moon), Some(wanted)) = (free_moon, wanted) {
+        if resources.spend(Expense::Building(wanted), balance.building_cost(wanted)) {
+            let build = PlayerAction::Build {
+                building: wanted,
+                moon,
+            };
+            pending.submit(build, sim_time.frame_number(), &mut transport);
+        }
+    }
+
+    // saving up for the next building
+    if bot.strategy == Strategy::Economy && free_moon.is_some() && wanted.is_some() {
+        return;
+    }
+    let targets: Vec<Vec2> = planet_query
+        .iter()
+        .filter(|(_, owner, _, _)| {
+            players.can_damage(local, owner.0) && !players.is_eliminated(owner.0)
+        })
+        .map(|(_, _, _, trans)| trans.translation.truncate())
+        .collect();
+    for (_, owner, moon, trans) in moon_query.iter() {
+        if owner.0 != local
+            || moon.building != Some(BuildingType::Production)
+            || resources.pink < balance.rocket_cost
+        {
+            continue;
+        }
+        let position = trans.translation.truncate();
+        let target = targets.iter().min_by(|a, b| {
+            let a = (**a - position).length();
+            let b = (**b - position).length();
+            a.partial_cmp(&b).unwrap()
+        });
+        let target = match target {
+            Some(&target) => target,
+            None => break,
+        };
+
+        // weaker bots miss their target by a random angle
+        let error = bot.difficulty.aim_error() * (2.0 * bot.rng.next_f32() - 1.0);
+        let aim = (target - position).normalize().extend(0.0);
+        let dir = Quat::from_rotation_z(error).mul_vec3(aim).truncate();
+        let launch = PlayerAction::ShootRocket {
+            pos: position,
+            dir,
+            power: 1.0,
+        };
+        resources.spend(Expense::Rocket, balance.rocket_cost);
+        pending.submit(launch, sim_time.frame_number(), &mut transport);
+    }
+}
@@ -1,17 +1,20 @@
 // Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
-use bevy::{
-    input::{keyboard::KeyboardInput, ElementState, Input},
-    prelude::*,
-};
+use bevy::{input::Input, prelude::*};
 use serde::{Deserialize, Serialize};
 
-use crate::components::{Moon, PlayerResources};
+use crate::balance::Balance;
+use crate::command_bar::GameCommand;
+use crate::components::{BodyId, Expense, Moon, Owner, PlayerResources};
 use crate::cursor_world_coords::*;
-use crate::network::{PlayerAction, Transport};
+use crate::input_map::{InputAction, InputActions};
+use crate::network::{NetworkSimulationTime, PendingActions, PlayerAction, Transport};
+use crate::picking::Picking;
+use crate::players::Players;
+use crate::selection::Selection;
 
-#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum BuildingType {
     Mining,
     Production,
@@ -19,37 +22,99 @@ pub enum BuildingType {
 
 #[derive(Default)]
 pub struct BuildingState {
-    keyboard_event_reader: EventReader<KeyboardInput>,
+    command_event_reader: EventReader<GameCommand>,
     cursor_follower: Option<Entity>,
     currently_building: Option<BuildingType>,
 }
 
+/// Orders the building on the given moon on the given frame if the player can afford it.
+fn submit_build(
+    building: BuildingType,
+    moon: u32,
+    frame: u32,
+    balance: &Balance,
+    resources: &mut PlayerResources,
+    pending: &mut PendingActions,
+    transport: &mut Transport,
+) {
+    if resources.spend(Expense::Building(building), balance.building_cost(building)) {
+        pending.submit(PlayerAction::Build { building, moon }, frame, transport);
+    }
+}
+
+/// Returns whether the local player can build on the moon, which has to be theirs and free, with
+/// no building pending for it either.
+fn can_build_on(
+    id: &BodyId,
+    owner: &Owner,
+    moon: &Moon,
+    players: &Players,
+    pending: &PendingActions,
+) -> bool {
+    let planned = pending.iter().any(|p| match p.action {
+        PlayerAction::Build { moon, .. } => moon == id.0,
+        _ => false,
+    });
+    owner.0 == players.local && moon.building.is_none() && !planned
+}
+
+/// System building on the selected moon, or on the next moon clicked if none is selected.
 pub fn building(
     commands: &mut Commands,
     mut state: Local<BuildingState>,
     cursor_in_world: Res<CursorInWorld>,
-    keyboard_inputs: Res<Events<KeyboardInput>>,
+    actions: Res<InputActions>,
+    command_events: Res<Events<GameCommand>>,
     mouse_input: Res<Input<MouseButton>>,
+    picking: Res<Picking>,
+    selection: Res<Selection>,
     texture_atlases: Res<Assets<TextureAtlas>>,
+    balance: Res<Balance>,
+    sim_time: Res<NetworkSimulationTime>,
     mut resources: ResMut<PlayerResources>,
     mut transport: ResMut<Transport>,
-    mut moon_query: Query<(Entity, &Moon, &GlobalTransform)>,
+    mut pending: ResMut<PendingActions>,
+    players: Res<Players>,
+    moon_query: Query<(&BodyId, &Owner, &Moon)>,
 ) {
     let world_coords = cursor_in_world.position;
 
-    // change to building mode on button press
-    for event in state.keyboard_event_reader.iter(&keyboard_inputs) {
-        if state.currently_building.is_none() && event.state == ElementState::Pressed {
-            state.currently_building = match event.key_code {
-                Some(KeyCode::B) => Some(BuildingType::Mining),
-                Some(KeyCode::R) => Some(BuildingType::Production),
-                _ => state.currently_building,
-            };
+    let mut requested = None;
+    if actions.just_pressed(InputAction::BuildMining) {
+        requested = Some(BuildingType::Mining);
+    }
+    if actions.just_pressed(InputAction::BuildProduction) {
+        requested = Some(BuildingType::Production);
+    }
+    for command in state.command_event_reader.iter(&command_events) {
+        if let GameCommand::Build(building) = *command {
+            requested = Some(building);
+        }
+    }
 
-            if let Some(building) = state.currently_building {
+    if let Some(building) = requested {
+        let selected = selection.selected().and_then(|entity| moon_query.get(entity).ok());
+        match selected {
+            // a free moon of the player which is selected is built on right away
+            Some((id, owner, moon)) if can_build_on(id, owner, moon, &players, &pending) => {
+                submit_build(
+                    building,
+                    id.0,
+                    sim_time.frame_number(),
+                    &balance,
+                    &mut resources,
+                    &mut pending,
+                    &mut transport,
+                );
+            }
+            // otherwise the building follows the cursor until a moon is clicked
+            _ if state.currently_building.is_none() => {
+                state.currently_building = Some(building);
                 state.cursor_follower = commands
                     .spawn(SpriteSheetBundle {
-                        sprite: building_cursor_texture(building),
+                        sprite: TextureAtlasSprite::new(
+                            balance.building_cursor_texture_index(building),
+                        ),
                         texture_atlas: texture_atlases.get_handle("SPRITE_SHEET"),
                         transform: Transform {
                             translation: world_coords.extend(0.0),
@@ -61,27 +126,24 @@ pub fn building(
                     .with(CursorFollowing)
                     .current_entity();
             }
+            _ => {}
         }
     }
 
     if let Some(building) = state.currently_building {
         if mouse_input.pressed(MouseButton::Left) {
-            // check if cursor is inside of a moon
-            // TODO: use actual sprite size instead of magic number
-            for (entity, _, trans) in moon_query.iter_mut() {
-                if trans.translation.x - 128.0 * trans.scale.x <= world_coords.x
-                    && trans.translation.x + 128.0 * trans.scale.x >= world_coords.x
-                    && trans.translation.y - 128.0 * trans.scale.y <= world_coords.y
-                    && trans.translation.y + 128.0 * trans.scale.y >= world_coords.y
-                    && resources.pink >= building_cost(building)
-                {
-                    let build = PlayerAction::Build {
+            let target = picking.hovered().and_then(|entity| moon_query.get(entity).ok());
+            if let Some((id, owner, moon)) = target {
+                if can_build_on(id, owner, moon, &players, &pending) {
+                    submit_build(
                         building,
-                        moon: entity.id(),
-                    };
-                    let serialized = bincode::serialize(&build).unwrap();
-                    transport.send(serialized);
-                    resources.pink -= building_cost(building);
+                        id.0,
+                        sim_time.frame_number(),
+                        &balance,
+                        &mut resources,
+                        &mut pending,
+                        &mut transport,
+                    );
                 }
             }
             commands.despawn(state.cursor_follower.unwrap());
@@ -89,24 +151,3 @@ pub fn building(
         }
     }
 }
-
-fn building_cursor_texture(building: BuildingType) -> TextureAtlasSprite {
-    match building {
-        BuildingType::Mining => TextureAtlasSprite::new(5),
-        BuildingType::Production => TextureAtlasSprite::new(12),
-    }
-}
-
-pub fn building_moon_texture_index(building: BuildingType) -> u32 {
-    match building {
-        BuildingType::Mining => 9,
-        BuildingType::Production => 8,
-    }
-}
-
-fn building_cost(building: BuildingType) -> u32 {
-    match building {
-        BuildingType::Mining => 20,
-        BuildingType::Production => 15,
-    }
-}
@@ -0,0 +1,257 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::{
+    input::{
+        mouse::{MouseScrollUnit, MouseWheel},
+        Input,
+    },
+    prelude::*,
+    render::camera::Camera,
+    ui::camera::UI_CAMERA,
+};
+
+use crate::components::{Owner, Planet};
+use crate::cursor_world_coords::CursorInWorld;
+use crate::input_map::{InputAction, InputActions};
+use crate::lobby_screen::ClientScreen;
+use crate::map::MapBounds;
+use crate::picking::PickingEvent;
+use crate::players::Players;
+
+/// Smallest and largest scale of the game camera, larger values show more of the world.
+pub const MIN_ZOOM: f32 = 0.5;
+pub const MAX_ZOOM: f32 = 4.0;
+
+/// Factor the camera scale changes by per line scrolled.
+const ZOOM_STEP: f32 = 1.1;
+
+/// Pixels of smooth scrolling (e.g. on touchpads) which count as one line.
+const PIXELS_PER_LINE: f32 = 20.0;
+
+/// Pixels per second the camera moves on screen when panning with the keys or the screen edges.
+const PAN_SPEED: f32 = 500.0;
+
+/// Distance in pixels from the window border at which the camera starts to scroll.
+const EDGE_SCROLL_MARGIN: f32 = 10.0;
+
+/// Seconds within which a second click on the same planet focuses the camera on it.
+const DOUBLE_CLICK_TIME: f64 = 0.3;
+
+/// How quickly the camera catches up with its goal, higher is faster.
+const SMOOTHING: f32 = 10.0;
+
+/// Where the game camera is heading, it follows smoothly.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraGoal {
+    pub position: Vec2,
+    pub zoom: f32,
+}
+
+impl Default for CameraGoal {
+    fn default() -> Self {
+        Self {
+            position: Vec2::splat(0.0),
+            zoom: 1.0,
+        }
+    }
+}
+
+/// This plugin moves the game camera with the arrow keys, the screen edges and by dragging with
+/// the middle mouse button, and zooms it with the mouse wheel.
+/// Double-clicking a planet focuses on it, and there is an action to jump back to the own planet.
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<CameraGoal>()
+            .init_resource::<MapBounds>()
+            .add_system(camera_motion)
+            .add_system(camera_focus)
+            .add_system(camera_zoom)
+            .add_system(camera_follow);
+    }
+}
+
+#[derive(Default)]
+pub struct CameraMotionState {
+    cursor_event_reader: EventReader<CursorMoved>,
+    /// Last known cursor position in the window
+    window_position: Option<Vec2>,
+    /// Cursor position in the window when the camera was last dragged
+    dragged_from: Option<Vec2>,
+}
+
+fn camera_motion(
+    mut state: Local<CameraMotionState>,
+    time: Res<Time>,
+    actions: Res<InputActions>,
+    mouse_input: Res<Input<MouseButton>>,
+    cursor_inputs: Res<Events<CursorMoved>>,
+    windows: Res<Windows>,
+    screen: Res<ClientScreen>,
+    mut goal: ResMut<CameraGoal>,
+    query: Query<(&Camera, &Transform)>,
+) {
+    if let Some(event) = state.cursor_event_reader.iter(&cursor_inputs).last() {
+        state.window_position = Some(event.position);
+    }
+
+    for (camera, trans) in query.iter() {
+        if camera.name == Some(UI_CAMERA.to_string()) {
+            continue;
+        }
+
+        // determine direction based on keyboard input
+        let mut direction = Vec3::splat(0.0);
+        if actions.pressed(InputAction::PanUp) {
+            direction += Vec3::new(0.0, 1.0, 0.0)
+        }
+        if actions.pressed(InputAction::PanDown) {
+            direction += Vec3::new(0.0, -1.0, 0.0)
+        }
+        if actions.pressed(InputAction::PanLeft) {
+            direction += Vec3::new(-1.0, 0.0, 0.0)
+        }
+        if actions.pressed(InputAction::PanRight) {
+            direction += Vec3::new(1.0, 0.0, 0.0)
+        }
+
+        // scroll when the cursor touches the window border during a match
+        if let (ClientScreen::InGame, Some(pos), Some(window)) =
+            (*screen, state.window_position, windows.get_primary())
+        {
+            let size = Vec2::new(window.width() as f32, window.height() as f32);
+            if pos.x < EDGE_SCROLL_MARGIN {
+                direction += Vec3::new(-1.0, 0.0, 0.0)
+            }
+            if pos.x > size.x - EDGE_SCROLL_MARGIN {
+                direction += Vec3::new(1.0, 0.0, 0.0)
+            }
+            if pos.y < EDGE_SCROLL_MARGIN {
+                direction += Vec3::new(0.0, -1.0, 0.0)
+            }
+            if pos.y > size.y - EDGE_SCROLL_MARGIN {
+                direction += Vec3::new(0.0, 1.0, 0.0)
+            }
+        }
+
+        // move the camera at constant speed on screen in determined direction
+        let ds = PAN_SPEED * time.delta_seconds * trans.scale.x;
+        if direction.length() > 0.0 {
+            goal.position += trans.rotation.mul_vec3(direction.normalize()).truncate() * ds;
+        }
+
+        // dragging keeps the point below the cursor
+        let dragging = mouse_input.pressed(MouseButton::Middle);
+        state.dragged_from = match (dragging, state.window_position) {
+            (true, Some(pos)) => {
+                if let Some(from) = state.dragged_from {
+                    let moved = (pos - from).extend(0.0) * trans.scale.x;
+                    goal.position -= trans.rotation.mul_vec3(moved).truncate();
+                }
+                Some(pos)
+            }
+            _ => None,
+        };
+    }
+}
+
+#[derive(Default)]
+pub struct CameraFocusState {
+    picking_event_reader: EventReader<PickingEvent>,
+    /// The entity clicked last and when
+    last_click: Option<(Entity, f64)>,
+}
+
+/// System moving the camera to a double-clicked planet or the own planet.
+fn camera_focus(
+    mut state: Local<CameraFocusState>,
+    time: Res<Time>,
+    picking_events: Res<Events<PickingEvent>>,
+    actions: Res<InputActions>,
+    players: Res<Players>,
+    mut goal: ResMut<CameraGoal>,
+    planet_query: Query<(&Planet, &Owner, &GlobalTransform)>,
+) {
+    let now = time.seconds_since_startup;
+    for event in state.picking_event_reader.iter(&picking_events) {
+        if let PickingEvent::Clicked(entity, MouseButton::Left) = *event {
+            let double_click = match state.last_click {
+                Some((last, at)) => last == entity && now - at <= DOUBLE_CLICK_TIME,
+                None => false,
+            };
+            if double_click {
+                if let Ok((_, _, trans)) = planet_query.get(entity) {
+                    goal.position = trans.translation.truncate();
+                }
+                state.last_click = None;
+            } else {
+                state.last_click = Some((entity, now));
+            }
+        }
+    }
+
+    if actions.just_pressed(InputAction::FocusHome) {
+        for (_, owner, trans) in planet_query.iter() {
+            if owner.0 == players.local {
+                goal.position = trans.translation.truncate();
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct CameraZoomState {
+    mouse_wheel_event_reader: EventReader<MouseWheel>,
+}
+
+/// System zooming the game camera with the mouse wheel, keeping the point below the cursor.
+fn camera_zoom(
+    mut state: Local<CameraZoomState>,
+    mouse_wheel_events: Res<Events<MouseWheel>>,
+    cursor_in_world: Res<CursorInWorld>,
+    mut goal: ResMut<CameraGoal>,
+) {
+    let lines: f32 = state
+        .mouse_wheel_event_reader
+        .iter(&mouse_wheel_events)
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / PIXELS_PER_LINE,
+        })
+        .sum();
+    if lines == 0.0 {
+        return;
+    }
+
+    // scrolling up zooms in
+    let zoom = (goal.zoom * ZOOM_STEP.powf(-lines)).max(MIN_ZOOM).min(MAX_ZOOM);
+    let factor = zoom / goal.zoom;
+    let cursor = cursor_in_world.position;
+    goal.position = cursor + (goal.position - cursor) * factor;
+    goal.zoom = zoom;
+}
+
+/// System moving the game camera smoothly towards its goal, which is kept within the map.
+fn camera_follow(
+    time: Res<Time>,
+    bounds: Res<MapBounds>,
+    mut goal: ResMut<CameraGoal>,
+    mut query: Query<(&Camera, Mut<Transform>)>,
+) {
+    goal.position = bounds.clamp(goal.position);
+
+    // the same share of the remaining way is covered per second, whatever the frame rate
+    let progress = 1.0 - (-SMOOTHING * time.delta_seconds).exp();
+    for (camera, mut trans) in query.iter_mut() {
+        if camera.name == Some(UI_CAMERA.to_string()) {
+            continue;
+        }
+        let position = trans.translation.truncate();
+        let position = position + (goal.position - position) * progress;
+        let scale = trans.scale.x + (goal.zoom - trans.scale.x) * progress;
+        trans.translation = position.extend(trans.translation.z);
+        trans.scale = Vec3::new(scale, scale, trans.scale.z);
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::VecDeque;
+
+use bevy::{
+    input::{keyboard::KeyboardInput, ElementState, Input},
+    prelude::*,
+    window::ReceivedCharacter,
+};
+
+use crate::cursor_world_coords::CursorInWorld;
+use crate::lobby_screen::{ClientScreen, LobbyMaterials};
+use crate::network::*;
+use crate::picking::Picking;
+use crate::players::Players;
+
+/// Seconds a chat message stays visible while the player is not typing.
+const CHAT_DISPLAY_TIME: f64 = 10.0;
+
+/// Number of chat messages kept in the log.
+const CHAT_LOG_LENGTH: usize = 8;
+
+/// Seconds a map ping stays visible.
+const MAP_PING_DURATION: f32 = 3.0;
+
+/// The chat message the player is currently typing, if any.
+#[derive(Default)]
+pub struct ChatInput {
+    text: Option<String>,
+    channel: Option<ChatChannel>,
+}
+
+impl ChatInput {
+    /// Returns whether key presses currently go into the chat instead of the game.
+    pub fn is_typing(&self) -> bool {
+        self.text.is_some()
+    }
+}
+
+struct ChatLine {
+    text: String,
+    color: Color,
+    received_at: f64,
+}
+
+/// Recently received chat messages.
+#[derive(Default)]
+pub struct ChatLog {
+    lines: VecDeque<ChatLine>,
+    /// Number of lines shown by the chat box
+    shown: usize,
+    changed: bool,
+}
+
+impl ChatLog {
+    fn push(&mut self, text: String, color: Color, now: f64) {
+        if self.lines.len() == CHAT_LOG_LENGTH {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(ChatLine {
+            text,
+            color,
+            received_at: now,
+        });
+        self.changed = true;
+    }
+}
+
+struct ChatRoot;
+
+/// Marks the position of a map ping until its timer finishes.
+struct MapPingMarker {
+    timer: Timer,
+}
+
+/// This plugin adds chatting with all players or the own team, as well as pinging the map.
+///
+/// Enter starts typing to everyone, Shift+Enter to the own team and Tab switches between both.
+/// Clicking while holding Alt pings the map at the cursor for the own team.
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ChatInput>()
+            .init_resource::<ChatLog>()
+            .add_system(chat_input)
+            .add_system(receive_chat)
+            .add_system(send_map_pings)
+            .add_system(map_ping_markers)
+            .add_system(chat_ui);
+    }
+}
+
+#[derive(Default)]
+pub struct ChatInputState {
+    character_event_reader: EventReader<ReceivedCharacter>,
+    keyboard_event_reader: EventReader<KeyboardInput>,
+}
+
+fn chat_input(
+    mut state: Local<ChatInputState>,
+    screen: Res<ClientScreen>,
+    character_events: Res<Events<ReceivedCharacter>>,
+    keyboard_events: Res<Events<KeyboardInput>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mut input: ResMut<ChatInput>,
+    mut log: ResMut<ChatLog>,
+    mut transport: ResMut<Transport>,
+) {
+    let in_game = *screen == ClientScreen::InGame;
+    for event in state.keyboard_event_reader.iter(&keyboard_events) {
+        if !in_game || event.state != ElementState::Pressed {
+            continue;
+        }
+        let shift = keyboard_input.pressed(KeyCode::LShift)
+            || keyboard_input.pressed(KeyCode::RShift);
+        let was_typing = input.is_typing();
+        match (event.key_code, input.text.take()) {
+            (Some(KeyCode::Return), None) => {
+                input.text = Some(String::new());
+                input.channel = Some(if shift { ChatChannel::Team } else { ChatChannel::All });
+            }
+            (Some(KeyCode::Return), Some(text)) => {
+                if !text.trim().is_empty() {
+                    let channel = input.channel.unwrap_or(ChatChannel::All);
+                    let message = ClientMessage::Chat { channel, text };
+                    transport.send(bincode::serialize(&message).unwrap());
+                }
+            }
+            (Some(KeyCode::Escape), Some(_)) => {}
+            (Some(KeyCode::Tab), Some(text)) => {
+                input.text = Some(text);
+                input.channel = match input.channel {
+                    Some(ChatChannel::Team) => Some(ChatChannel::All),
+                    _ => Some(ChatChannel::Team),
+                };
+            }
+            (Some(KeyCode::Back), Some(mut text)) => {
+                text.pop();
+                input.text = Some(text);
+            }
+            (_, text) => input.text = text,
+        }
+        if was_typing || input.is_typing() {
+            log.changed = true;
+        }
+    }
+
+    for event in state.character_event_reader.iter(&character_events) {
+        if let Some(text) = &mut input.text {
+            if !event.char.is_control() && text.chars().count() < MAX_CHAT_LENGTH {
+                text.push(event.char);
+                log.changed = true;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ReceiveChatState {
+    chat_event_reader: EventReader<ChatMessage>,
+    map_ping_event_reader: EventReader<MapPing>,
+}
+
+/// System adding received chat messages to the log and showing received map pings.
+fn receive_chat(
+    commands: &mut Commands,
+    mut state: Local<ReceiveChatState>,
+    time: Res<Time>,
+    chat_events: Res<Events<ChatMessage>>,
+    map_ping_events: Res<Events<MapPing>>,
+    players: Res<Players>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut log: ResMut<ChatLog>,
+) {
+    let now = time.seconds_since_startup;
+    for message in state.chat_event_reader.iter(&chat_events) {
+        let name = players.get(message.player).map_or("Someone", |p| p.name.as_str());
+        let text = match message.channel {
+            ChatChannel::All => format!("{}: {}", name, message.text),
+            ChatChannel::Team => format!("[Team] {}: {}", name, message.text),
+        };
+        log.push(text, players.color(message.player), now);
+    }
+
+    for ping in state.map_ping_event_reader.iter(&map_ping_events) {
+        let color = players.color(ping.player);
+        commands
+            .spawn(SpriteBundle {
+                material: materials.add(color.into()),
+                sprite: Sprite::new(Vec2::new(40.0, 40.0)),
+                transform: Transform {
+                    translation: ping.position.extend(0.5),
+                    rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_4),
+                    scale: Vec3::splat(1.0),
+                },
+                ..Default::default()
+            })
+            .with(MapPingMarker {
+                timer: Timer::from_seconds(MAP_PING_DURATION, false),
+            });
+        let name = players.get(ping.player).map_or("Someone", |p| p.name.as_str());
+        log.push(format!("{} pinged the map", name), color, now);
+    }
+}
+
+/// System sending a map ping when the player clicks while holding Alt.
+fn send_map_pings(
+    screen: Res<ClientScreen>,
+    picking: Res<Picking>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    cursor_in_world: Res<CursorInWorld>,
+    mut transport: ResMut<Transport>,
+) {
+    let alt = keyboard_input.pressed(KeyCode::LAlt) || keyboard_input.pressed(KeyCode::RAlt);
+    let clicked = alt && mouse_input.just_pressed(MouseButton::Left);
+    // clicks on UI panels like the minimap send their own pings
+    if *screen == ClientScreen::InGame && clicked && !picking.is_blocked() {
+        let message = ClientMessage::MapPing {
+            position: cursor_in_world.position,
+        };
+        transport.send(bincode::serialize(&message).unwrap());
+    }
+}
+
+/// System letting map pings pulse until they disappear.
+fn map_ping_markers(
+    commands: &mut Commands,
+    time: Res<Time>,
+    mut marker_query: Query<(Entity, Mut<MapPingMarker>, Mut<Transform>)>,
+) {
+    for (entity, mut marker, mut trans) in marker_query.iter_mut() {
+        if marker.timer.tick(time.delta_seconds).finished() {
+            commands.despawn(entity);
+            continue;
+        }
+        let pulse = (marker.timer.elapsed() * 2.0 * std::f32::consts::PI).sin();
+        trans.scale = Vec3::splat(1.0 + 0.3 * pulse);
+    }
+}
+
+/// System rebuilding the chat box whenever messages arrive, expire or are typed.
+fn chat_ui(
+    commands: &mut Commands,
+    time: Res<Time>,
+    screen: Res<ClientScreen>,
+    materials: Res<LobbyMaterials>,
+    input: Res<ChatInput>,
+    mut log: ResMut<ChatLog>,
+    root_query: Query<(Entity, &ChatRoot)>,
+) {
+    let now = time.seconds_since_startup;
+    let visible: Vec<_> = log
+        .lines
+        .iter()
+        .filter(|line| input.is_typing() || now - line.received_at < CHAT_DISPLAY_TIME)
+        .map(|line| (line.text.clone(), line.color))
+        .collect();
+    if !log.changed && visible.len() == log.shown {
+        return;
+    }
+    let shown = visible.len();
+
+    for (entity, _) in root_query.iter() {
+        commands.despawn_recursive(entity);
+    }
+    if *screen == ClientScreen::InGame {
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(10.0),
+                        bottom: Val::Px(10.0),
+                        ..Default::default()
+                    },
+                    flex_direction: FlexDirection::ColumnReverse,
+                    ..Default::default()
+                },
+                material: materials.background.clone(),
+                ..Default::default()
+            })
+            .with(ChatRoot)
+            .with_children(|parent| {
+                for (text, color) in visible {
+                    spawn_chat_line(parent, &materials, &text, color);
+                }
+                if let Some(text) = &input.text {
+                    let channel = match input.channel {
+                        Some(ChatChannel::Team) => "Team",
+                        _ => "All",
+                    };
+                    let line = format!("[{}] {}_", channel, text);
+                    spawn_chat_line(parent, &materials, &line, Color::WHITE);
+                }
+            });
+    }
+    log.shown = shown;
+    log.changed = false;
+}
+
+fn spawn_chat_line(
+    parent: &mut ChildBuilder,
+    materials: &LobbyMaterials,
+    value: &str,
+    color: Color,
+) {
+    parent.spawn(TextBundle {
+        text: Text {
+            value: value.to_string(),
+            font: materials.font.clone(),
+            style: TextStyle {
+                font_size: 22.0,
+                color,
+                alignment: TextAlignment::default(),
+            },
+        },
+        ..Default::default()
+    });
+}
@@ -1,80 +1,392 @@
 // Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
-use bevy::{
-    input::{keyboard::KeyboardInput, ElementState, Input},
-    prelude::*,
-};
+use bevy::{input::Input, prelude::*};
 
+use crate::balance::Balance;
 use crate::building::*;
+use crate::command_bar::GameCommand;
 use crate::components::*;
 use crate::cursor_world_coords::*;
-use crate::network::{PlayerAction, Transport};
+use crate::flight::{with_moons, FlightBody, FlightOutcome, FlightSpace};
+use crate::input_map::{InputAction, InputActions};
+use crate::lobby_screen::{ClientScreen, LobbyMaterials};
+use crate::network::{NetworkSimulationTime, PendingActions, PlayerAction, Transport};
+use crate::picking::Picking;
+use crate::players::Players;
+use crate::selection::Selection;
+
+/// Smallest share of the full rocket speed a rocket can be launched with.
+const MIN_LAUNCH_POWER: f32 = 0.25;
+
+/// Distance between the cursor and the aiming moon at which rockets are launched at full power.
+const FULL_POWER_DISTANCE: f32 = 600.0;
+
+/// Simulation frames the trajectory preview looks ahead at most.
+const PREVIEW_FRAMES: u32 = 600;
+
+/// Simulation frames between the dots of the trajectory preview.
+const PREVIEW_DOT_SPACING: usize = 3;
+
+/// Returns the share of the full rocket speed a launch with the given power gets.
+pub fn launch_power(power: f32) -> f32 {
+    power.max(MIN_LAUNCH_POWER).min(1.0)
+}
+
+/// A rocket being aimed from a production moon, launched towards the cursor.
+#[derive(Default)]
+pub struct RocketAim {
+    from: Option<Entity>,
+    target: Vec2,
+    /// Share of the full rocket speed, set by the cursor's distance from the moon
+    power: f32,
+}
+
+pub struct AimMaterials {
+    dot: Handle<ColorMaterial>,
+    impact: Handle<ColorMaterial>,
+}
+
+impl FromResources for AimMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        AimMaterials {
+            dot: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.6).into()),
+            impact: materials.add(Color::rgb(1.0, 0.3, 0.2).into()),
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct CombatState {
-    keyboard_event_reader: EventReader<KeyboardInput>,
-    current_rocket_base: Option<Entity>,
+    command_event_reader: EventReader<GameCommand>,
+}
+
+/// Launches a rocket from the given position towards the target on the given frame if the player
+/// can afford it.
+fn submit_launch(
+    from: Vec2,
+    target: Vec2,
+    power: f32,
+    frame: u32,
+    balance: &Balance,
+    resources: &mut PlayerResources,
+    pending: &mut PendingActions,
+    transport: &mut Transport,
+) {
+    if !resources.spend(Expense::Rocket, balance.rocket_cost) {
+        return;
+    }
+    let launch = PlayerAction::ShootRocket {
+        pos: from,
+        dir: (target - from).normalize(),
+        power,
+    };
+    pending.submit(launch, frame, transport);
+}
+
+/// Returns the frame a launch issued now is probably executed on and where the launching moon
+/// is on that frame, which is where the rocket starts.
+fn expected_launch(
+    moon: Entity,
+    bodies: &[FlightBody],
+    sim_time: &NetworkSimulationTime,
+    pending: &PendingActions,
+) -> Option<(u32, Vec2)> {
+    let frame = pending.expected_frame(sim_time.frame_number());
+    let body = bodies.iter().find(|body| body.entity == moon)?;
+    Some((frame, body.position(frame as f32, sim_time.per_frame_duration())))
 }
 
-/// System for shooting rockets in mouse cursor direction.
+/// System for aiming and shooting rockets from the selected production moon.
+///
+/// The launch action or the rocket command of the command bar start aiming, the rocket is then
+/// launched towards the cursor with the next click or launch action.
+/// The further the cursor is from the moon, the faster the rocket flies.
 pub fn combat(
-    commands: &mut Commands,
     mut state: Local<CombatState>,
-    time: Res<Time>,
-    keyboard_inputs: Res<Events<KeyboardInput>>,
+    mut aim: ResMut<RocketAim>,
+    actions: Res<InputActions>,
+    command_events: Res<Events<GameCommand>>,
+    screen: Res<ClientScreen>,
     mouse_input: Res<Input<MouseButton>>,
+    picking: Res<Picking>,
+    selection: Res<Selection>,
+    balance: Res<Balance>,
     mut resources: ResMut<PlayerResources>,
     cursor_in_world: Res<CursorInWorld>,
+    sim_time: Res<NetworkSimulationTime>,
     mut transport: ResMut<Transport>,
-    moon_query: Query<(Entity, &Moon, &GlobalTransform)>,
-    mut rocket_query: Query<(Entity, &Rocket, Mut<Transform>)>,
+    mut pending: ResMut<PendingActions>,
+    players: Res<Players>,
+    moon_query: Query<(&Moon, &Owner, &GlobalTransform)>,
+    planet_body_query: Query<(Entity, &Owner, &Planet, &Transform)>,
+    moon_body_query: Query<(Entity, &Owner, &Moon, &Transform, &Parent)>,
 ) {
-    for event in state.keyboard_event_reader.iter(&keyboard_inputs) {
-        if event.key_code == Some(KeyCode::A) && event.state == ElementState::Pressed {
-            if resources.pink < 3 || state.current_rocket_base.is_none() {
-                continue;
-            }
-            resources.pink -= 3;
-
-            let base_moon = state.current_rocket_base.unwrap();
-            let (_, _, trans) = moon_query.get(base_moon).unwrap();
-            let rocket_position = trans.translation;
-            let rocket_direction =
-                (cursor_in_world.position - trans.translation.truncate()).normalize();
-
-            let launch = PlayerAction::ShootRocket {
-                pos: rocket_position.truncate(),
-                dir: rocket_direction,
-            };
-            let serialized = bincode::serialize(&launch).unwrap();
-            transport.send(serialized);
+    let launch_pressed = actions.just_pressed(InputAction::LaunchRocket);
+    let mut commanded = false;
+    for command in state.command_event_reader.iter(&command_events) {
+        commanded |= *command == GameCommand::LaunchRocket;
+    }
+
+    let from = match aim.from.filter(|&entity| moon_query.get(entity).is_ok()) {
+        Some(entity) => entity,
+        None => {
+            // the click or key starting to aim does not launch yet
+            aim.from = selection.selected().filter(|&entity| {
+                let moon = moon_query.get(entity).ok();
+                (launch_pressed || commanded)
+                    && moon.map_or(false, |(moon, owner, _)| {
+                        moon.building == Some(BuildingType::Production)
+                            && owner.0 == players.local
+                    })
+            });
+            return;
         }
+    };
+    if mouse_input.just_pressed(MouseButton::Right) || *screen != ClientScreen::InGame {
+        aim.from = None;
+        return;
+    }
+
+    // the rocket starts where the moon is once the launch is executed, not where it is now
+    let planets = planet_body_query
+        .iter()
+        .map(|(entity, owner, _, trans)| FlightBody::planet(entity, owner.0, trans))
+        .collect();
+    let bodies = with_moons(planets, moon_body_query.iter());
+    let (_, from) = match expected_launch(from, &bodies, &sim_time, &pending) {
+        Some(launch) => launch,
+        None => return,
+    };
+    aim.target = cursor_in_world.position;
+    aim.power = launch_power((aim.target - from).length() / FULL_POWER_DISTANCE);
+    let clicked = mouse_input.just_pressed(MouseButton::Left) && !picking.is_blocked();
+    if (clicked || launch_pressed) && aim.target != from {
+        submit_launch(
+            from,
+            aim.target,
+            aim.power,
+            sim_time.frame_number(),
+            &balance,
+            &mut resources,
+            &mut pending,
+            &mut transport,
+        );
+        aim.from = None;
     }
+}
 
-    let world_coords = cursor_in_world.position;
-    if mouse_input.pressed(MouseButton::Left) {
-        // check if cursor is inside of a moon
-        // TODO: use actual sprite size instead of magic number
-        for (entity, moon, trans) in moon_query.iter() {
-            if trans.translation.x - 128.0 * trans.scale.x <= world_coords.x
-                && trans.translation.x + 128.0 * trans.scale.x >= world_coords.x
-                && trans.translation.y - 128.0 * trans.scale.y <= world_coords.y
-                && trans.translation.y + 128.0 * trans.scale.y >= world_coords.y
-                && moon.building == Some(BuildingType::Production)
-            {
-                //sprite.index = ...;
-                state.current_rocket_base = Some(entity);
+/// A dot of the trajectory preview.
+struct AimDot;
+
+/// Where the previewed rocket hits a planet or moon.
+struct AimImpact;
+
+struct AimPowerText;
+
+#[derive(Default)]
+pub struct AimPreviewState {
+    dots: Vec<Entity>,
+    impact: Option<Entity>,
+    power_text: Option<Entity>,
+}
+
+/// System showing where an aimed rocket would fly and what it would hit, taking the motion of
+/// the moons into account.
+pub fn aim_preview(
+    commands: &mut Commands,
+    mut state: Local<AimPreviewState>,
+    aim: Res<RocketAim>,
+    sim_time: Res<NetworkSimulationTime>,
+    pending: Res<PendingActions>,
+    balance: Res<Balance>,
+    players: Res<Players>,
+    materials: Res<AimMaterials>,
+    lobby_materials: Res<LobbyMaterials>,
+    planet_query: Query<(Entity, &Owner, &Planet, &Transform)>,
+    moon_query: Query<(Entity, &Owner, &Moon, &Transform, &Parent)>,
+    mut dot_query: Query<(&AimDot, Mut<Transform>)>,
+    mut impact_query: Query<(&AimImpact, Mut<Transform>)>,
+    mut text_query: Query<(&AimPowerText, Mut<Text>)>,
+) {
+    let planets = planet_query
+        .iter()
+        .map(|(entity, owner, _, trans)| FlightBody::planet(entity, owner.0, trans))
+        .collect();
+    let bodies = with_moons(planets, moon_query.iter());
+    // the preview starts from the frame the launch is expected to be executed on
+    let launch = aim
+        .from
+        .and_then(|entity| expected_launch(entity, &bodies, &sim_time, &pending));
+    let (frame, from) = match launch {
+        Some((frame, from)) if aim.target != from => (frame, from),
+        _ => {
+            for entity in state.dots.drain(..) {
+                commands.despawn(entity);
+            }
+            if let Some(entity) = state.impact.take() {
+                commands.despawn(entity);
+            }
+            if let Some(entity) = state.power_text.take() {
+                commands.despawn_recursive(entity);
+            }
+            return;
+        }
+    };
+
+    let space = FlightSpace {
+        bodies: &bodies,
+        players: &players,
+        balance: &balance,
+        per_frame_duration: sim_time.per_frame_duration(),
+    };
+    let velocity = balance.rocket_speed * aim.power * (aim.target - from).normalize();
+    let rocket = Rocket::new(from, velocity, frame);
+    let (positions, hit) = space.predict(&rocket, players.local, PREVIEW_FRAMES);
+
+    // dots are reused as long as there are enough of them
+    let dot_positions: Vec<_> = positions.iter().step_by(PREVIEW_DOT_SPACING).collect();
+    while state.dots.len() > dot_positions.len() {
+        commands.despawn(state.dots.pop().unwrap());
+    }
+    for (i, position) in dot_positions.into_iter().enumerate() {
+        let translation = position.extend(0.5);
+        match state.dots.get(i).and_then(|&dot| dot_query.get_mut(dot).ok()) {
+            Some((_, mut trans)) => trans.translation = translation,
+            None => {
+                commands
+                    .spawn(SpriteBundle {
+                        sprite: Sprite::new(Vec2::new(8.0, 8.0)),
+                        material: materials.dot.clone(),
+                        transform: Transform::from_translation(translation),
+                        ..Default::default()
+                    })
+                    .with(AimDot);
+                state.dots.push(commands.current_entity().unwrap());
             }
         }
     }
 
-    // move rockets according to their current velocity
-    for (entity, rocket, mut trans) in rocket_query.iter_mut() {
-        trans.translation += rocket.velocity.extend(0.0) * time.delta_seconds;
-        // despawn if out of bounds
-        if trans.translation.length() > 2000.0 {
+    let impact = hit.and(positions.last());
+    match (impact, state.impact) {
+        (Some(position), Some(entity)) => {
+            if let Ok((_, mut trans)) = impact_query.get_mut(entity) {
+                trans.translation = position.extend(0.6);
+            }
+        }
+        (Some(position), None) => {
+            commands
+                .spawn(SpriteBundle {
+                    sprite: Sprite::new(Vec2::new(24.0, 24.0)),
+                    material: materials.impact.clone(),
+                    transform: Transform {
+                        translation: position.extend(0.6),
+                        rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_4),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .with(AimImpact);
+            state.impact = commands.current_entity();
+        }
+        (None, Some(entity)) => {
             commands.despawn(entity);
+            state.impact = None;
+        }
+        (None, None) => {}
+    }
+
+    let value = format!(
+        "Power {:.0}% - click to launch, right click to cancel",
+        aim.power * 100.0
+    );
+    match state.power_text.and_then(|entity| text_query.get_mut(entity).ok()) {
+        Some((_, mut text)) => text.value = value,
+        None if state.power_text.is_none() => {
+            commands
+                .spawn(TextBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            left: Val::Px(10.0),
+                            bottom: Val::Px(100.0),
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    },
+                    text: Text {
+                        value,
+                        font: lobby_materials.font.clone(),
+                        style: TextStyle {
+                            font_size: 24.0,
+                            color: Color::WHITE,
+                            alignment: TextAlignment::default(),
+                        },
+                    },
+                    ..Default::default()
+                })
+                .with(AimPowerText);
+            state.power_text = commands.current_entity();
+        }
+        None => {}
+    }
+}
+
+/// System moving rockets under the gravity of the planets and moons and letting them hit those.
+/// Hits are checked once per simulation frame, so that all clients agree on them.
+pub fn rocket_flight(
+    commands: &mut Commands,
+    sim_time: Res<NetworkSimulationTime>,
+    balance: Res<Balance>,
+    mut players: ResMut<Players>,
+    mut rocket_query: Query<(Entity, Mut<Rocket>, &Owner, Mut<Transform>)>,
+    mut planet_query: Query<(Entity, &Owner, Mut<Planet>, &Transform, Mut<TextureAtlasSprite>)>,
+    moon_query: Query<(Entity, &Owner, &Moon, &Transform, &Parent)>,
+) {
+    let per_frame = sim_time.per_frame_duration();
+    let planets = planet_query
+        .iter_mut()
+        .map(|(entity, owner, _, trans, _)| FlightBody::planet(entity, owner.0, trans))
+        .collect();
+    let bodies = with_moons(planets, moon_query.iter());
+    let space = FlightSpace {
+        bodies: &bodies,
+        players: &players,
+        balance: &balance,
+        per_frame_duration: per_frame,
+    };
+
+    let mut hits = vec![];
+    for (entity, mut rocket, attacker, mut trans) in rocket_query.iter_mut() {
+        let mut outcome = FlightOutcome::Flying;
+        while outcome == FlightOutcome::Flying && rocket.checked_frame < sim_time.frame_number() {
+            outcome = space.step(&mut rocket, attacker.0);
+        }
+        match outcome {
+            FlightOutcome::Flying => {
+                // rockets move smoothly in between the simulation frames hits are checked on
+                let position = rocket.extrapolate(sim_time.elapsed_duration());
+                trans.translation = position.extend(0.0);
+                trans.rotation = Quat::from_rotation_z(rocket.velocity.y.atan2(rocket.velocity.x));
+            }
+            FlightOutcome::Hit(body) => {
+                hits.push((attacker.0, body));
+                commands.despawn(entity);
+            }
+            FlightOutcome::Expired => commands.despawn(entity),
+        }
+    }
+
+    // moons only stop rockets, planets are damaged
+    for (attacker, body) in hits {
+        if let Ok((_, victim, mut planet, _, mut sprite)) = planet_query.get_mut(body) {
+            planet.health = planet.health.saturating_sub(balance.rocket_damage);
+            if planet.health == 0 && !players.is_eliminated(victim.0) {
+                info!("Player {} was eliminated by player {}", victim.0, attacker);
+                players.eliminate(victim.0);
+                sprite.color = Color::rgb(0.3, 0.3, 0.3);
+            }
         }
     }
 }
@@ -0,0 +1,252 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::{input::Input, prelude::*};
+
+use crate::balance::Balance;
+use crate::building::BuildingType;
+use crate::components::{Aura, Moon, Owner, Planet, PlayerResources};
+use crate::input_map::{InputAction, InputMap};
+use crate::lobby_screen::{spawn_label, ClientScreen, LobbyMaterials};
+use crate::minimap::{MINIMAP_MARGIN, MINIMAP_SIZE};
+use crate::picking::Picking;
+use crate::players::Players;
+use crate::selection::Selection;
+
+/// Something the player can do with the selected planet or moon.
+/// It is sent as an event when clicked in the command bar and has the same effect as its
+/// input action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameCommand {
+    Build(BuildingType),
+    /// Aim a rocket from the selected production moon, it is launched with the next click
+    LaunchRocket,
+    ChangeAura(Aura),
+}
+
+impl GameCommand {
+    pub fn label(&self) -> &'static str {
+        match self {
+            GameCommand::Build(BuildingType::Mining) => "Mining",
+            GameCommand::Build(BuildingType::Production) => "Production",
+            GameCommand::LaunchRocket => "Rocket",
+            GameCommand::ChangeAura(Aura::ProductionSpeed) => "Production speed",
+            GameCommand::ChangeAura(Aura::RocketSpeed) => "Rocket speed",
+            GameCommand::ChangeAura(Aura::RocketDamage) => "Rocket damage",
+            GameCommand::ChangeAura(Aura::MoonSpeed) => "Moon speed",
+            GameCommand::ChangeAura(Aura::Shield) => "Shield",
+        }
+    }
+
+    /// Returns the input action doing the same.
+    pub fn action(&self) -> InputAction {
+        match self {
+            GameCommand::Build(BuildingType::Mining) => InputAction::BuildMining,
+            GameCommand::Build(BuildingType::Production) => InputAction::BuildProduction,
+            GameCommand::LaunchRocket => InputAction::LaunchRocket,
+            GameCommand::ChangeAura(Aura::ProductionSpeed) => InputAction::AuraProductionSpeed,
+            GameCommand::ChangeAura(Aura::RocketSpeed) => InputAction::AuraRocketSpeed,
+            GameCommand::ChangeAura(Aura::RocketDamage) => InputAction::AuraRocketDamage,
+            GameCommand::ChangeAura(Aura::MoonSpeed) => InputAction::AuraMoonSpeed,
+            GameCommand::ChangeAura(Aura::Shield) => InputAction::AuraShield,
+        }
+    }
+
+    /// Returns the pink crystals the command costs.
+    pub fn cost(&self, balance: &Balance) -> u32 {
+        match self {
+            GameCommand::Build(building) => balance.building_cost(*building),
+            GameCommand::LaunchRocket => balance.rocket_cost,
+            GameCommand::ChangeAura(_) => 0,
+        }
+    }
+}
+
+/// Returns the commands available for a body of the local player.
+fn available_commands(planet: Option<&Planet>, moon: Option<&Moon>) -> Vec<GameCommand> {
+    match (planet, moon) {
+        (Some(_), _) => vec![
+            GameCommand::ChangeAura(Aura::ProductionSpeed),
+            GameCommand::ChangeAura(Aura::RocketSpeed),
+            GameCommand::ChangeAura(Aura::RocketDamage),
+            GameCommand::ChangeAura(Aura::MoonSpeed),
+            GameCommand::ChangeAura(Aura::Shield),
+        ],
+        (_, Some(moon)) => match moon.building {
+            None => vec![
+                GameCommand::Build(BuildingType::Mining),
+                GameCommand::Build(BuildingType::Production),
+            ],
+            Some(BuildingType::Production) => vec![GameCommand::LaunchRocket],
+            Some(BuildingType::Mining) => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+/// Returns the commands available for the selected body, if it belongs to the local player and
+/// they are still in the match.
+pub fn selected_commands(
+    screen: &ClientScreen,
+    selection: &Selection,
+    players: &Players,
+    body_query: &Query<(&Owner, Option<&Planet>, Option<&Moon>)>,
+) -> Vec<GameCommand> {
+    let body = selection.selected().and_then(|entity| body_query.get(entity).ok());
+    match body {
+        Some((owner, planet, moon))
+            if *screen == ClientScreen::InGame
+                && owner.0 == players.local
+                && !players.is_eliminated(owner.0) =>
+        {
+            available_commands(planet, moon)
+        }
+        _ => vec![],
+    }
+}
+
+pub struct CommandBarMaterials {
+    disabled: Handle<ColorMaterial>,
+}
+
+impl FromResources for CommandBarMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        CommandBarMaterials {
+            disabled: materials.add(Color::rgb(0.2, 0.2, 0.25).into()),
+        }
+    }
+}
+
+struct CommandBar;
+
+struct CommandButton {
+    command: GameCommand,
+    enabled: bool,
+}
+
+/// This plugin shows a bar of buttons for the commands available for the selected planet or
+/// moon, which send `GameCommand` events when clicked.
+/// Commands the player cannot afford are shown disabled.
+pub struct CommandBarPlugin;
+
+impl Plugin for CommandBarPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<GameCommand>()
+            .init_resource::<CommandBarMaterials>()
+            .add_system(command_bar)
+            .add_system(command_buttons);
+    }
+}
+
+#[derive(Default)]
+pub struct CommandBarState {
+    /// The commands shown and whether they are enabled
+    shown: Vec<(GameCommand, bool)>,
+}
+
+/// System rebuilding the command bar whenever the available commands or their costs change.
+fn command_bar(
+    commands: &mut Commands,
+    mut state: Local<CommandBarState>,
+    screen: Res<ClientScreen>,
+    selection: Res<Selection>,
+    players: Res<Players>,
+    balance: Res<Balance>,
+    resources: Res<PlayerResources>,
+    input_map: Res<InputMap>,
+    lobby_materials: Res<LobbyMaterials>,
+    materials: Res<CommandBarMaterials>,
+    body_query: Query<(&Owner, Option<&Planet>, Option<&Moon>)>,
+    bar_query: Query<(Entity, &CommandBar)>,
+) {
+    let shown: Vec<_> = selected_commands(&screen, &selection, &players, &body_query)
+        .into_iter()
+        .map(|command| (command, resources.pink >= command.cost(&balance)))
+        .collect();
+    if shown == state.shown {
+        return;
+    }
+
+    for (entity, _) in bar_query.iter() {
+        commands.despawn_recursive(entity);
+    }
+    if !shown.is_empty() {
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    // leaves the bottom right corner to the minimap
+                    position: Rect {
+                        left: Val::Px(MINIMAP_MARGIN),
+                        right: Val::Px(MINIMAP_SIZE + 2.0 * MINIMAP_MARGIN),
+                        bottom: Val::Px(10.0),
+                        ..Default::default()
+                    },
+                    justify_content: JustifyContent::Center,
+                    ..Default::default()
+                },
+                material: lobby_materials.background.clone(),
+                ..Default::default()
+            })
+            .with(CommandBar)
+            .with_children(|parent| {
+                for &(command, enabled) in shown.iter() {
+                    let material = if enabled {
+                        lobby_materials.button.clone()
+                    } else {
+                        materials.disabled.clone()
+                    };
+                    parent
+                        .spawn(ButtonBundle {
+                            style: Style {
+                                margin: Rect::all(Val::Px(5.0)),
+                                padding: Rect::all(Val::Px(4.0)),
+                                flex_direction: FlexDirection::ColumnReverse,
+                                align_items: AlignItems::Center,
+                                ..Default::default()
+                            },
+                            material,
+                            ..Default::default()
+                        })
+                        .with(CommandButton { command, enabled })
+                        .with_children(|parent| {
+                            let hotkey = input_map.hotkey(command.action());
+                            let label = format!("{} [{}]", command.label(), hotkey);
+                            spawn_label(parent, &lobby_materials, &label, 22.0);
+                            let cost = match command.cost(&balance) {
+                                0 => "free".to_string(),
+                                cost => format!("{} pink", cost),
+                            };
+                            spawn_label(parent, &lobby_materials, &cost, 18.0);
+                        });
+                }
+            });
+    }
+    state.shown = shown;
+}
+
+/// System sending the commands of clicked buttons.
+fn command_buttons(
+    mouse_input: Res<Input<MouseButton>>,
+    mut picking: ResMut<Picking>,
+    mut resources: ResMut<PlayerResources>,
+    mut command_events: ResMut<Events<GameCommand>>,
+    button_query: Query<(&Interaction, &CommandButton)>,
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction == Interaction::None {
+            continue;
+        }
+        // sprites in the world below the buttons cannot be clicked
+        picking.block();
+        if *interaction != Interaction::Clicked || !mouse_input.just_pressed(MouseButton::Left) {
+            continue;
+        }
+        if button.enabled {
+            command_events.send(button.command);
+        } else {
+            resources.unaffordable += 1;
+        }
+    }
+}
@@ -1,14 +1,37 @@
 // Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
+use std::collections::HashMap;
+
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::building::*;
 
-#[derive(Default)]
+/// Identifies a planet or moon the same way on all clients, unlike its entity id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BodyId(pub u32);
+
+/// Hit points of a planet at the start of a match.
+pub const PLANET_HEALTH: u32 = 100;
+
+/// Number of the player a planet, moon or rocket belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Owner(pub u8);
+
 pub struct Planet {
     pub current_aura: Option<Aura>,
+    /// Remaining hit points, the owner is eliminated once they reach zero
+    pub health: u32,
+}
+
+impl Default for Planet {
+    fn default() -> Self {
+        Self {
+            current_aura: None,
+            health: PLANET_HEALTH,
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
@@ -20,18 +43,78 @@ pub enum Aura {
     Shield,
 }
 
+#[derive(Clone, Debug)]
 pub struct Moon {
     pub orbit_radius: f32,
     pub speed: f64,
     pub building: Option<BuildingType>,
 }
 
+impl Moon {
+    /// Returns the position of the moon relative to its planet at the given simulation time.
+    pub fn offset(&self, time: f64) -> Vec2 {
+        let ds = self.speed * time;
+        Vec2::new(self.orbit_radius * ds.cos() as f32, self.orbit_radius * ds.sin() as f32)
+    }
+}
+
+/// A flying rocket, which is moved by gravity once per simulation frame.
+#[derive(Clone, Debug)]
 pub struct Rocket {
+    /// Position on the last simulation frame checked
+    pub position: Vec2,
+    /// Velocity on the last simulation frame checked
     pub velocity: Vec2,
+    pub launch_position: Vec2,
+    /// Simulation frame the rocket was launched on
+    pub launch_frame: u32,
+    /// Last simulation frame the rocket was moved and checked for hits on
+    pub checked_frame: u32,
+}
+
+impl Rocket {
+    pub fn new(position: Vec2, velocity: Vec2, frame: u32) -> Self {
+        Self {
+            position,
+            velocity,
+            launch_position: position,
+            launch_frame: frame,
+            checked_frame: frame,
+        }
+    }
+
+    /// Returns where the rocket is the given seconds after the last simulation frame checked,
+    /// ignoring gravity in between.
+    pub fn extrapolate(&self, seconds: f32) -> Vec2 {
+        self.position + self.velocity * seconds
+    }
+}
+
+/// Something the player spends pink crystals on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Expense {
+    Building(BuildingType),
+    Rocket,
 }
 
-pub struct ResourcesText;
 pub struct PlayerResources {
     pub pink: u32,
     pub green: u32,
+    /// Pink crystals spent on each expense during the match
+    pub spent: HashMap<Expense, u32>,
+    /// Number of times the player tried to spend more than they had
+    pub unaffordable: u32,
+}
+
+impl PlayerResources {
+    /// Pays the given pink crystals for the expense, if the player can afford it.
+    pub fn spend(&mut self, expense: Expense, cost: u32) -> bool {
+        if self.pink < cost {
+            self.unaffordable += 1;
+            return false;
+        }
+        self.pink -= cost;
+        *self.spent.entry(expense).or_insert(0) += cost;
+        true
+    }
 }
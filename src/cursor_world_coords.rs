@@ -17,37 +17,48 @@ pub struct CursorInWorld {
 #[derive(Default)]
 pub struct CursorState {
     cursor_event_reader: EventReader<CursorMoved>,
+    /// Last known cursor position in the window, measured from its bottom left corner
+    window_position: Option<Vec2>,
 }
 
+/// System converting the cursor position to world coordinates.
+///
+/// The conversion is repeated every frame, because the position in the world also changes when
+/// the camera moves, zooms or rotates, or the window is resized.
 pub fn cursor_world_coords(
     mut state: Local<CursorState>,
     mut cursor_in_world: ResMut<CursorInWorld>,
     cursor_inputs: Res<Events<CursorMoved>>,
-    camera_query: Query<(&Camera, &Transform, &OrthographicProjection)>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform, &OrthographicProjection)>,
     mut cursorfollowing_query: Query<(&CursorFollowing, Mut<Transform>)>,
 ) {
     if let Some(event) = state.cursor_event_reader.iter(&cursor_inputs).last() {
-        let cursor_position = event.position;
-
-        // get the releveant attributes of the 2D orth. projection
-        let mut camera_pos = Vec2::splat(0.0);
-        let mut camera_width = 0.0;
-        let mut camera_height = 0.0;
-        for (camera, trans, orth) in camera_query.iter() {
-            if camera.name == Some(UI_CAMERA.to_string()) {
-                continue;
-            }
-
-            camera_pos = Vec2::new(trans.translation.x, trans.translation.y);
-            camera_width = orth.right - orth.left;
-            camera_height = orth.top - orth.bottom;
+        state.window_position = Some(event.position);
+    }
+    let (window_position, window) = match (state.window_position, windows.get_primary()) {
+        (Some(position), Some(window)) => (position, window),
+        _ => return,
+    };
+    let window_size = Vec2::new(window.width() as f32, window.height() as f32);
+
+    for (camera, trans, orth) in camera_query.iter() {
+        if camera.name == Some(UI_CAMERA.to_string()) {
+            continue;
         }
 
-        // convert cursor position in window to world coordinates
-        let x = cursor_position.x;
-        let y = cursor_position.y;
-        let screen_coords = Vec2::new(x - camera_width / 2.0, y - camera_height / 2.0);
-        let world_coords = camera_pos + screen_coords;
+        // the projection spans the whole window, so the cursor is at the same fraction of it
+        let fraction = window_position / window_size;
+        let camera_coords = Vec3::new(
+            orth.left + fraction.x * (orth.right - orth.left),
+            orth.bottom + fraction.y * (orth.top - orth.bottom),
+            0.0,
+        );
+        // the camera's transform takes care of its position, zoom and rotation
+        let world_coords = trans.mul_vec3(camera_coords).truncate();
+        if world_coords == cursor_in_world.position {
+            return;
+        }
 
         // assign the new world coords to the gloabl resource
         cursor_in_world.position = world_coords;
@@ -0,0 +1,156 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::prelude::*;
+
+use crate::balance::Balance;
+use crate::components::{Moon, Owner, Rocket};
+use crate::players::Players;
+
+/// Distance from the center of a body (at scale 1) within which rockets hit it.
+const HIT_RADIUS: f32 = 128.0;
+
+/// A planet or moon as seen by flying rockets.
+///
+/// Moon positions are computed from their orbits for the simulation frame in question, so that
+/// all clients agree on hits no matter when they render.
+#[derive(Clone, Debug)]
+pub struct FlightBody {
+    pub entity: Entity,
+    pub owner: u8,
+    /// Center of the planet, or of the planet the moon orbits
+    center: Vec2,
+    moon: Option<Moon>,
+    radius: f32,
+}
+
+impl FlightBody {
+    pub fn planet(entity: Entity, owner: u8, trans: &Transform) -> Self {
+        Self {
+            entity,
+            owner,
+            center: trans.translation.truncate(),
+            moon: None,
+            radius: HIT_RADIUS * trans.scale.x,
+        }
+    }
+
+    /// The moon's transform is relative to its planet.
+    pub fn moon(entity: Entity, owner: u8, moon: &Moon, trans: &Transform, planet: &Self) -> Self {
+        Self {
+            entity,
+            owner,
+            center: planet.center,
+            moon: Some(moon.clone()),
+            radius: planet.radius * trans.scale.x,
+        }
+    }
+
+    /// Returns the position of the body on the given (possibly fractional) simulation frame.
+    pub fn position(&self, frame: f32, per_frame_duration: f32) -> Vec2 {
+        match &self.moon {
+            Some(moon) => self.center + moon.offset(frame as f64 * per_frame_duration as f64),
+            None => self.center,
+        }
+    }
+}
+
+/// Adds the moons orbiting the given planets to them.
+pub fn with_moons<'a>(
+    mut bodies: Vec<FlightBody>,
+    moons: impl Iterator<Item = (Entity, &'a Owner, &'a Moon, &'a Transform, &'a Parent)>,
+) -> Vec<FlightBody> {
+    let mut moon_bodies = vec![];
+    for (entity, owner, moon, trans, parent) in moons {
+        if let Some(planet) = bodies.iter().find(|body| body.entity == parent.0) {
+            moon_bodies.push(FlightBody::moon(entity, owner.0, moon, trans, planet));
+        }
+    }
+    bodies.extend(moon_bodies);
+    bodies
+}
+
+/// What happened to a rocket on a simulation frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlightOutcome {
+    Flying,
+    /// The rocket hit the body with the given entity
+    Hit(Entity),
+    /// The rocket left its range or flew for too long
+    Expired,
+}
+
+/// The world a rocket flies through.
+pub struct FlightSpace<'a> {
+    pub bodies: &'a [FlightBody],
+    pub players: &'a Players,
+    pub balance: &'a Balance,
+    pub per_frame_duration: f32,
+}
+
+impl<'a> FlightSpace<'a> {
+    /// Returns the acceleration of a rocket at the given position by the gravity of all bodies.
+    fn gravity(&self, position: Vec2, frame: f32) -> Vec2 {
+        self.bodies
+            .iter()
+            .fold(Vec2::splat(0.0), |acceleration, body| {
+                let strength = match body.moon {
+                    Some(_) => self.balance.moon_gravity,
+                    None => self.balance.planet_gravity,
+                };
+                let offset = body.position(frame, self.per_frame_duration) - position;
+                // within a body the pull stays as strong as on its surface
+                let distance = offset.length().max(body.radius);
+                acceleration + offset * (strength / (distance * distance * distance))
+            })
+    }
+
+    /// Moves the rocket on by one simulation frame.
+    ///
+    /// The same fixed step (semi-implicit Euler, which keeps orbits stable) is taken on every
+    /// client, so that all of them agree on where rockets go.
+    /// Rockets pass through the planets and moons of their owner and of their owner's allies,
+    /// but are pulled by all of them.
+    pub fn step(&self, rocket: &mut Rocket, owner: u8) -> FlightOutcome {
+        let dt = self.per_frame_duration;
+        let acceleration = self.gravity(rocket.position, rocket.checked_frame as f32);
+        rocket.velocity += acceleration * dt;
+        rocket.position += rocket.velocity * dt;
+        rocket.checked_frame += 1;
+
+        let frame = rocket.checked_frame as f32;
+        let hit = self.bodies.iter().find(|body| {
+            let distance = (body.position(frame, dt) - rocket.position).length();
+            distance <= body.radius && self.players.can_damage(owner, body.owner)
+        });
+        let flight_time = (rocket.checked_frame - rocket.launch_frame) as f32 * dt;
+        let distance = (rocket.position - rocket.launch_position).length();
+        match hit {
+            Some(body) => FlightOutcome::Hit(body.entity),
+            None if distance > self.balance.rocket_range
+                || flight_time > self.balance.rocket_lifetime =>
+            {
+                FlightOutcome::Expired
+            }
+            None => FlightOutcome::Flying,
+        }
+    }
+
+    /// Follows the rocket for up to the given number of simulation frames.
+    ///
+    /// Returns the positions on all frames and the body hit at the end, if any.
+    pub fn predict(&self, rocket: &Rocket, owner: u8, frames: u32) -> (Vec<Vec2>, Option<Entity>) {
+        let mut rocket = rocket.clone();
+        let mut positions = vec![];
+        for _ in 0..frames {
+            let outcome = self.step(&mut rocket, owner);
+            positions.push(rocket.position);
+            match outcome {
+                FlightOutcome::Flying => {}
+                FlightOutcome::Hit(entity) => return (positions, Some(entity)),
+                FlightOutcome::Expired => break,
+            }
+        }
+        (positions, None)
+    }
+}
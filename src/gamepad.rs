@@ -0,0 +1,401 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::f32::consts::PI;
+
+use bevy::{
+    app::stage,
+    input::{
+        gamepad::{
+            Gamepad, GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType, GamepadEvent,
+            GamepadEventType,
+        },
+        mouse::MouseButtonInput,
+        Axis, ElementState, Input,
+    },
+    prelude::*,
+};
+
+use crate::balance::Balance;
+use crate::camera::{CameraGoal, MAX_ZOOM, MIN_ZOOM};
+use crate::command_bar::{selected_commands, GameCommand};
+use crate::components::{Moon, Owner, Planet, PlayerResources};
+use crate::lobby_screen::{spawn_label, ClientScreen, LobbyMaterials};
+use crate::players::Players;
+use crate::selection::Selection;
+
+/// Stick deflection below which the stick counts as centered.
+const DEADZONE: f32 = 0.15;
+
+/// Pixels per second the camera moves on screen with the left stick fully deflected.
+const STICK_PAN_SPEED: f32 = 800.0;
+
+/// Pixels per second the virtual cursor moves with the right stick fully deflected.
+const CURSOR_SPEED: f32 = 600.0;
+
+/// Factor the camera scale changes by per second while a bumper is held.
+const ZOOM_SPEED: f32 = 2.0;
+
+/// Size of the virtual cursor in pixels.
+const CURSOR_SIZE: f32 = 12.0;
+
+/// Distance in pixels of the radial menu's options from the center of the window.
+const RADIAL_RADIUS: f32 = 150.0;
+
+/// Size of an option of the radial menu in pixels.
+const RADIAL_OPTION_WIDTH: f32 = 180.0;
+const RADIAL_OPTION_HEIGHT: f32 = 40.0;
+
+/// The controller used for playing, which is the first one connected.
+#[derive(Default)]
+pub struct ActiveGamepad(pub Option<Gamepad>);
+
+pub struct GamepadMaterials {
+    cursor: Handle<ColorMaterial>,
+    highlighted: Handle<ColorMaterial>,
+    disabled: Handle<ColorMaterial>,
+}
+
+impl FromResources for GamepadMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        GamepadMaterials {
+            cursor: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.8).into()),
+            highlighted: materials.add(Color::rgb(0.35, 0.45, 0.8).into()),
+            disabled: materials.add(Color::rgb(0.2, 0.2, 0.25).into()),
+        }
+    }
+}
+
+/// The commands for the selected body arranged in a circle, open while the west button is held.
+#[derive(Default)]
+pub struct RadialMenu {
+    open: bool,
+    /// The commands shown and whether they are enabled
+    options: Vec<(GameCommand, bool)>,
+    /// Index of the option the right stick points at
+    highlighted: Option<usize>,
+}
+
+struct VirtualCursor;
+
+struct RadialMenuOption;
+
+/// This plugin lets the player use a controller:
+/// The left stick moves the camera and the bumpers zoom it.
+/// The right stick moves a virtual cursor, which acts like the mouse cursor, and the south button
+/// clicks with it.
+/// Holding the west button opens a radial menu of the selected body's commands, the one the
+/// right stick points at is sent when the button is released.
+/// Other buttons are bound to input actions by the `InputMap`.
+pub struct GamepadPlugin;
+
+impl Plugin for GamepadPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // the virtual cursor moves before any system of the frame uses the cursor position
+        app.init_resource::<GamepadMaterials>()
+            .init_resource::<RadialMenu>()
+            .add_system(active_gamepad)
+            .add_system(gamepad_camera)
+            .add_system_to_stage(stage::PRE_UPDATE, virtual_cursor)
+            .add_system(radial_menu)
+            .add_system(radial_menu_ui);
+    }
+}
+
+/// Returns the position of the left or right stick, or zero within the deadzone.
+fn stick(axes: &Axis<GamepadAxis>, gamepad: Gamepad, left: bool) -> Vec2 {
+    let (x, y) = if left {
+        (GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY)
+    } else {
+        (GamepadAxisType::RightStickX, GamepadAxisType::RightStickY)
+    };
+    let x = axes.get(GamepadAxis(gamepad, x)).unwrap_or(0.0);
+    let y = axes.get(GamepadAxis(gamepad, y)).unwrap_or(0.0);
+    let position = Vec2::new(x, y);
+    if position.length() < DEADZONE {
+        Vec2::splat(0.0)
+    } else {
+        position
+    }
+}
+
+#[derive(Default)]
+pub struct ActiveGamepadState {
+    gamepad_event_reader: EventReader<GamepadEvent>,
+}
+
+/// System keeping track of the controller used for playing.
+fn active_gamepad(
+    mut state: Local<ActiveGamepadState>,
+    gamepad_events: Res<Events<GamepadEvent>>,
+    mut active: ResMut<ActiveGamepad>,
+) {
+    for GamepadEvent(gamepad, event_type) in state.gamepad_event_reader.iter(&gamepad_events) {
+        match event_type {
+            GamepadEventType::Connected if active.0.is_none() => {
+                info!("Using controller {:?}", gamepad);
+                active.0 = Some(*gamepad);
+            }
+            GamepadEventType::Disconnected if active.0 == Some(*gamepad) => {
+                info!("Controller {:?} was disconnected", gamepad);
+                active.0 = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// System moving the camera with the left stick and zooming it with the bumpers.
+fn gamepad_camera(
+    time: Res<Time>,
+    screen: Res<ClientScreen>,
+    active: Res<ActiveGamepad>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<Input<GamepadButton>>,
+    mut goal: ResMut<CameraGoal>,
+) {
+    let gamepad = match (*screen, active.0) {
+        (ClientScreen::InGame, Some(gamepad)) => gamepad,
+        _ => return,
+    };
+
+    let direction = stick(&axes, gamepad, true);
+    goal.position += direction * STICK_PAN_SPEED * time.delta_seconds * goal.zoom;
+
+    let mut zoom_direction = 0.0;
+    if buttons.pressed(GamepadButton(gamepad, GamepadButtonType::LeftTrigger)) {
+        zoom_direction += 1.0;
+    }
+    if buttons.pressed(GamepadButton(gamepad, GamepadButtonType::RightTrigger)) {
+        zoom_direction -= 1.0;
+    }
+    let zoom = goal.zoom * ZOOM_SPEED.powf(zoom_direction * time.delta_seconds);
+    goal.zoom = zoom.max(MIN_ZOOM).min(MAX_ZOOM);
+}
+
+#[derive(Default)]
+pub struct VirtualCursorState {
+    cursor_event_reader: EventReader<CursorMoved>,
+    /// Position of the virtual cursor in the window, measured from its bottom left corner
+    position: Option<Vec2>,
+    /// The UI node showing the virtual cursor
+    node: Option<Entity>,
+}
+
+/// System moving a virtual cursor with the right stick.
+///
+/// It sends the same events as the mouse, so that everything reacting to the mouse cursor
+/// (e.g. `CursorInWorld`, picking and the UI) works with it as well.
+/// Moving the mouse moves the virtual cursor to the mouse cursor.
+fn virtual_cursor(
+    commands: &mut Commands,
+    mut state: Local<VirtualCursorState>,
+    time: Res<Time>,
+    screen: Res<ClientScreen>,
+    active: Res<ActiveGamepad>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<Input<GamepadButton>>,
+    radial_menu: Res<RadialMenu>,
+    windows: Res<Windows>,
+    materials: Res<GamepadMaterials>,
+    mut cursor_events: ResMut<Events<CursorMoved>>,
+    mut mouse_button_events: ResMut<Events<MouseButtonInput>>,
+    mut cursor_query: Query<(&VirtualCursor, Mut<Style>)>,
+) {
+    // this also sees the events sent below, which are at the same position anyway
+    if let Some(event) = state.cursor_event_reader.iter(&cursor_events).last() {
+        state.position = Some(event.position);
+    }
+    let (gamepad, window) = match (*screen, active.0, windows.get_primary()) {
+        (ClientScreen::InGame, Some(gamepad), Some(window)) => (gamepad, window),
+        _ => {
+            if let Some(node) = state.node.take() {
+                commands.despawn(node);
+            }
+            return;
+        }
+    };
+    let window_size = Vec2::new(window.width() as f32, window.height() as f32);
+    let position = state.position.unwrap_or(window_size / 2.0);
+
+    // the right stick chooses from the radial menu while it is open
+    if !radial_menu.open {
+        let direction = stick(&axes, gamepad, false);
+        let moved = position + direction * CURSOR_SPEED * time.delta_seconds;
+        let moved = moved.max(Vec2::splat(0.0)).min(window_size);
+        if Some(moved) != state.position {
+            cursor_events.send(CursorMoved {
+                id: window.id(),
+                position: moved,
+            });
+        }
+        state.position = Some(moved);
+
+        let click = GamepadButton(gamepad, GamepadButtonType::South);
+        let button_state = if buttons.just_pressed(click) {
+            Some(ElementState::Pressed)
+        } else if buttons.just_released(click) {
+            Some(ElementState::Released)
+        } else {
+            None
+        };
+        if let Some(button_state) = button_state {
+            mouse_button_events.send(MouseButtonInput {
+                button: MouseButton::Left,
+                state: button_state,
+            });
+        }
+    }
+
+    // the mouse cursor is drawn by the system, the virtual one needs a node of its own
+    let position = state.position.unwrap_or(position);
+    let node_position = Rect {
+        left: Val::Px(position.x - CURSOR_SIZE / 2.0),
+        bottom: Val::Px(position.y - CURSOR_SIZE / 2.0),
+        ..Default::default()
+    };
+    match state.node.and_then(|node| cursor_query.get_mut(node).ok()) {
+        Some((_, mut style)) => style.position = node_position,
+        None => {
+            commands
+                .spawn(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: node_position,
+                        size: Size::new(Val::Px(CURSOR_SIZE), Val::Px(CURSOR_SIZE)),
+                        ..Default::default()
+                    },
+                    material: materials.cursor.clone(),
+                    ..Default::default()
+                })
+                .with(VirtualCursor);
+            state.node = commands.current_entity();
+        }
+    }
+}
+
+/// Returns the option of the radial menu the given stick direction points at.
+fn radial_option(direction: Vec2, options: usize) -> Option<usize> {
+    if direction.length() == 0.0 || options == 0 {
+        return None;
+    }
+    // the first option is at the top, the others follow clockwise
+    let angle = (PI / 2.0 - direction.y.atan2(direction.x)).rem_euclid(2.0 * PI);
+    let step = 2.0 * PI / options as f32;
+    Some(((angle / step).round() as usize) % options)
+}
+
+/// Returns the center of the given option of the radial menu, relative to the window center.
+fn radial_offset(index: usize, options: usize) -> Vec2 {
+    let angle = PI / 2.0 - index as f32 * 2.0 * PI / options as f32;
+    Vec2::new(angle.cos(), angle.sin()) * RADIAL_RADIUS
+}
+
+/// System opening the radial menu while the west button is held and sending the command chosen.
+fn radial_menu(
+    screen: Res<ClientScreen>,
+    active: Res<ActiveGamepad>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<Input<GamepadButton>>,
+    selection: Res<Selection>,
+    players: Res<Players>,
+    balance: Res<Balance>,
+    mut resources: ResMut<PlayerResources>,
+    mut menu: ResMut<RadialMenu>,
+    mut command_events: ResMut<Events<GameCommand>>,
+    body_query: Query<(&Owner, Option<&Planet>, Option<&Moon>)>,
+) {
+    let gamepad = match active.0 {
+        Some(gamepad) => gamepad,
+        None => {
+            *menu = RadialMenu::default();
+            return;
+        }
+    };
+    let menu_button = GamepadButton(gamepad, GamepadButtonType::West);
+    menu.options = selected_commands(&screen, &selection, &players, &body_query)
+        .into_iter()
+        .map(|command| (command, resources.pink >= command.cost(&balance)))
+        .collect();
+
+    if buttons.just_released(menu_button) && menu.open {
+        let chosen = menu.highlighted.and_then(|index| menu.options.get(index));
+        match chosen {
+            Some(&(command, true)) => command_events.send(command),
+            Some(&(_, false)) => resources.unaffordable += 1,
+            None => {}
+        }
+    }
+    menu.open = buttons.pressed(menu_button) && !menu.options.is_empty();
+    menu.highlighted = if menu.open {
+        let direction = stick(&axes, gamepad, false);
+        radial_option(direction, menu.options.len())
+    } else {
+        None
+    };
+}
+
+#[derive(Default)]
+pub struct RadialMenuUiState {
+    /// The options shown and the highlighted one
+    shown: Option<(Vec<(GameCommand, bool)>, Option<usize>)>,
+}
+
+/// System rebuilding the radial menu whenever it opens, closes or its highlight changes.
+fn radial_menu_ui(
+    commands: &mut Commands,
+    mut state: Local<RadialMenuUiState>,
+    menu: Res<RadialMenu>,
+    windows: Res<Windows>,
+    lobby_materials: Res<LobbyMaterials>,
+    materials: Res<GamepadMaterials>,
+    option_query: Query<(Entity, &RadialMenuOption)>,
+) {
+    let shown = if menu.open {
+        Some((menu.options.clone(), menu.highlighted))
+    } else {
+        None
+    };
+    if shown == state.shown {
+        return;
+    }
+
+    for (entity, _) in option_query.iter() {
+        commands.despawn_recursive(entity);
+    }
+    if let (Some((options, highlighted)), Some(window)) = (&shown, windows.get_primary()) {
+        let center = Vec2::new(window.width() as f32, window.height() as f32) / 2.0;
+        let size = Vec2::new(RADIAL_OPTION_WIDTH, RADIAL_OPTION_HEIGHT);
+        for (index, &(command, enabled)) in options.iter().enumerate() {
+            let material = match (enabled, *highlighted == Some(index)) {
+                (false, _) => materials.disabled.clone(),
+                (true, true) => materials.highlighted.clone(),
+                (true, false) => lobby_materials.button.clone(),
+            };
+            let corner = center + radial_offset(index, options.len()) - size / 2.0;
+            commands
+                .spawn(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            left: Val::Px(corner.x),
+                            bottom: Val::Px(corner.y),
+                            ..Default::default()
+                        },
+                        size: Size::new(Val::Px(size.x), Val::Px(size.y)),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        ..Default::default()
+                    },
+                    material,
+                    ..Default::default()
+                })
+                .with(RadialMenuOption)
+                .with_children(|parent| {
+                    spawn_label(parent, &lobby_materials, command.label(), 22.0);
+                });
+        }
+    }
+    state.shown = shown;
+}
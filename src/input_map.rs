@@ -0,0 +1,438 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+use bevy::{
+    app::stage,
+    input::{
+        gamepad::{Gamepad, GamepadButton, GamepadButtonType},
+        Input,
+    },
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::chat::ChatInput;
+use crate::components::Planet;
+use crate::gamepad::ActiveGamepad;
+use crate::lobby_screen::ClientScreen;
+use crate::selection::Selection;
+
+/// The user's key bindings, which replace the default bindings of the actions they list.
+pub const INPUT_CONFIG_FILE: &str = "input.ron";
+
+/// Something the player does with a key, mouse button or controller button during a match.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InputAction {
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    FocusHome,
+    Deselect,
+    BuildMining,
+    BuildProduction,
+    LaunchRocket,
+    AuraProductionSpeed,
+    AuraRocketSpeed,
+    AuraRocketDamage,
+    AuraMoonSpeed,
+    AuraShield,
+    TogglePause,
+    SlowDown,
+    SpeedUp,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 17] = [
+        InputAction::PanUp,
+        InputAction::PanDown,
+        InputAction::PanLeft,
+        InputAction::PanRight,
+        InputAction::FocusHome,
+        InputAction::Deselect,
+        InputAction::BuildMining,
+        InputAction::BuildProduction,
+        InputAction::LaunchRocket,
+        InputAction::AuraProductionSpeed,
+        InputAction::AuraRocketSpeed,
+        InputAction::AuraRocketDamage,
+        InputAction::AuraMoonSpeed,
+        InputAction::AuraShield,
+        InputAction::TogglePause,
+        InputAction::SlowDown,
+        InputAction::SpeedUp,
+    ];
+
+    pub fn context(self) -> InputContext {
+        match self {
+            InputAction::BuildMining | InputAction::BuildProduction | InputAction::LaunchRocket => {
+                InputContext::Moons
+            }
+            InputAction::AuraProductionSpeed
+            | InputAction::AuraRocketSpeed
+            | InputAction::AuraRocketDamage
+            | InputAction::AuraMoonSpeed
+            | InputAction::AuraShield => InputContext::Planet,
+            _ => InputContext::Global,
+        }
+    }
+
+    fn default_bindings(self) -> Vec<Binding> {
+        use GamepadButtonType::*;
+        let keys = match self {
+            InputAction::PanUp => vec![KeyCode::Up],
+            InputAction::PanDown => vec![KeyCode::Down],
+            InputAction::PanLeft => vec![KeyCode::Left],
+            InputAction::PanRight => vec![KeyCode::Right],
+            InputAction::FocusHome => vec![KeyCode::H, KeyCode::Home],
+            InputAction::Deselect => vec![KeyCode::Escape],
+            InputAction::BuildMining => vec![KeyCode::B],
+            InputAction::BuildProduction => vec![KeyCode::R],
+            InputAction::LaunchRocket => vec![KeyCode::A],
+            InputAction::AuraProductionSpeed => vec![KeyCode::P],
+            InputAction::AuraRocketSpeed => vec![KeyCode::R],
+            InputAction::AuraRocketDamage => vec![KeyCode::D],
+            InputAction::AuraMoonSpeed => vec![KeyCode::M],
+            InputAction::AuraShield => vec![KeyCode::S],
+            InputAction::TogglePause => vec![KeyCode::Space],
+            InputAction::SlowDown => vec![KeyCode::Minus, KeyCode::Subtract],
+            InputAction::SpeedUp => vec![KeyCode::Equals, KeyCode::Add],
+        };
+        // the camera, selection and building have their own controller handling
+        let pad_buttons = match self {
+            InputAction::FocusHome => vec![DPadUp],
+            InputAction::Deselect => vec![East],
+            InputAction::LaunchRocket => vec![RightTrigger2],
+            InputAction::TogglePause => vec![Start],
+            InputAction::SlowDown => vec![DPadLeft],
+            InputAction::SpeedUp => vec![DPadRight],
+            _ => vec![],
+        };
+        let keys = keys.into_iter().map(Binding::Key);
+        keys.chain(pad_buttons.into_iter().map(Binding::Pad)).collect()
+    }
+}
+
+/// When the bindings of an action are active, so that the same key can mean different things.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputContext {
+    /// During the whole match
+    Global,
+    /// While no planet is selected, for building on and shooting from moons
+    Moons,
+    /// While a planet is selected
+    Planet,
+}
+
+impl InputContext {
+    /// Returns whether both contexts can be active at the same time.
+    pub fn overlaps(self, other: InputContext) -> bool {
+        self == InputContext::Global || other == InputContext::Global || self == other
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// A button of the active controller
+    Pad(GamepadButtonType),
+}
+
+/// The state of all inputs that bindings can refer to.
+struct RawInputs<'a> {
+    keys: &'a Input<KeyCode>,
+    buttons: &'a Input<MouseButton>,
+    pad_buttons: &'a Input<GamepadButton>,
+    gamepad: Option<Gamepad>,
+}
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)?) => {
+        fn key_from_name(name: &str) -> Option<KeyCode> {
+            match name {
+                $(stringify!($key) => Some(KeyCode::$key),)*
+                _ => None,
+            }
+        }
+
+        fn key_name(key: KeyCode) -> Option<&'static str> {
+            match key {
+                $(KeyCode::$key => Some(stringify!($key)),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+macro_rules! pad_button_names {
+    ($($button:ident),* $(,)?) => {
+        fn pad_button_from_name(name: &str) -> Option<GamepadButtonType> {
+            match name {
+                $(concat!("Pad", stringify!($button)) => Some(GamepadButtonType::$button),)*
+                _ => None,
+            }
+        }
+
+        fn pad_button_name(button: GamepadButtonType) -> Option<&'static str> {
+            match button {
+                $(GamepadButtonType::$button => Some(concat!("Pad", stringify!($button))),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+pad_button_names!(
+    South, East, North, West, LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2, Select,
+    Start, LeftThumb, RightThumb, DPadUp, DPadDown, DPadLeft, DPadRight,
+);
+
+key_names!(
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, A, B, C, D, E, F, G, H, I, J, K, L,
+    M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10,
+    F11, F12, Insert, Home, Delete, End, PageDown, PageUp, Left, Up, Right, Down, Back, Return,
+    Space, Tab, Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8,
+    Numpad9, Add, Subtract, Minus, Equals, Comma, Period, Slash, Backslash, Semicolon, Apostrophe,
+    Grave, LBracket, RBracket, LAlt, LControl, LShift, RAlt, RControl, RShift,
+);
+
+impl Binding {
+    /// Parses a binding as written in the config file, e.g. `"B"`, `"MouseRight"` or
+    /// `"PadSouth"`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "MouseLeft" => Some(Binding::Mouse(MouseButton::Left)),
+            "MouseRight" => Some(Binding::Mouse(MouseButton::Right)),
+            "MouseMiddle" => Some(Binding::Mouse(MouseButton::Middle)),
+            _ => key_from_name(name)
+                .map(Binding::Key)
+                .or_else(|| pad_button_from_name(name).map(Binding::Pad)),
+        }
+    }
+
+    fn pressed(self, inputs: &RawInputs) -> bool {
+        match self {
+            Binding::Key(key) => inputs.keys.pressed(key),
+            Binding::Mouse(button) => inputs.buttons.pressed(button),
+            Binding::Pad(button) => inputs.gamepad.map_or(false, |gamepad| {
+                inputs.pad_buttons.pressed(GamepadButton(gamepad, button))
+            }),
+        }
+    }
+
+    fn just_pressed(self, inputs: &RawInputs) -> bool {
+        match self {
+            Binding::Key(key) => inputs.keys.just_pressed(key),
+            Binding::Mouse(button) => inputs.buttons.just_pressed(button),
+            Binding::Pad(button) => inputs.gamepad.map_or(false, |gamepad| {
+                inputs.pad_buttons.just_pressed(GamepadButton(gamepad, button))
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Binding::Key(key) => match key_name(*key) {
+                Some(name) => write!(f, "{}", name),
+                None => write!(f, "{:?}", key),
+            },
+            Binding::Mouse(MouseButton::Left) => write!(f, "MouseLeft"),
+            Binding::Mouse(MouseButton::Right) => write!(f, "MouseRight"),
+            Binding::Mouse(MouseButton::Middle) => write!(f, "MouseMiddle"),
+            Binding::Mouse(button) => write!(f, "{:?}", button),
+            Binding::Pad(button) => match pad_button_name(*button) {
+                Some(name) => write!(f, "{}", name),
+                None => write!(f, "{:?}", button),
+            },
+        }
+    }
+}
+
+/// Contents of the config file, for example
+/// `(bindings: { LaunchRocket: ["F"], FocusHome: ["C"] })`.
+#[derive(Deserialize, Serialize, Default)]
+struct InputConfig {
+    bindings: HashMap<InputAction, Vec<String>>,
+}
+
+/// The keys and mouse buttons bound to each action.
+#[derive(Clone, Debug)]
+pub struct InputMap {
+    bindings: HashMap<InputAction, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let bindings = InputAction::ALL
+            .iter()
+            .map(|&action| (action, action.default_bindings()))
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl InputMap {
+    /// Reads the user's bindings from the given RON file on top of the default bindings.
+    ///
+    /// Unknown key names are skipped. So that every key does only one thing at a time, a default
+    /// binding which conflicts with one of the user's is dropped, and of two conflicting user
+    /// bindings the one of the earlier action in `InputAction::ALL` is kept.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let config: InputConfig =
+            ron::de::from_str(&contents).map_err(|e| format!("Invalid input config: {}", e))?;
+
+        let mut map = Self::default();
+        let user_actions: HashSet<_> = config.bindings.keys().copied().collect();
+        for (action, names) in config.bindings {
+            let bindings = names
+                .iter()
+                .filter_map(|name| {
+                    let binding = Binding::parse(name);
+                    if binding.is_none() {
+                        warn!("Unknown key {:?} bound to {:?}", name, action);
+                    }
+                    binding
+                })
+                .collect();
+            map.bindings.insert(action, bindings);
+        }
+        for (first, second, binding) in map.conflicts() {
+            let only_second_by_user =
+                user_actions.contains(&second) && !user_actions.contains(&first);
+            let (kept, dropped) = if only_second_by_user {
+                (second, first)
+            } else {
+                (first, second)
+            };
+            warn!("{} is bound to both {:?} and {:?}, keeping {:?}", binding, first, second, kept);
+            if let Some(bindings) = map.bindings.get_mut(&dropped) {
+                bindings.retain(|&b| b != binding);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn bindings(&self, action: InputAction) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], |bindings| bindings.as_slice())
+    }
+
+    /// Returns the name of the first binding of the action, e.g. to show it next to a button.
+    pub fn hotkey(&self, action: InputAction) -> String {
+        self.bindings(action).first().map_or("-".to_string(), |binding| binding.to_string())
+    }
+
+    /// Returns the pairs of actions which share a binding while both can be active.
+    pub fn conflicts(&self) -> Vec<(InputAction, InputAction, Binding)> {
+        let mut conflicts = vec![];
+        for (i, &first) in InputAction::ALL.iter().enumerate() {
+            for &second in InputAction::ALL[i + 1..].iter() {
+                if !first.context().overlaps(second.context()) {
+                    continue;
+                }
+                for &binding in self.bindings(first) {
+                    if self.bindings(second).contains(&binding) {
+                        conflicts.push((first, second, binding));
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+/// The actions whose bindings are held down or were pressed this frame, in the active contexts.
+#[derive(Default)]
+pub struct InputActions {
+    pressed: HashSet<InputAction>,
+    just_pressed: HashSet<InputAction>,
+}
+
+impl InputActions {
+    pub fn pressed(&self, action: InputAction) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+/// This plugin translates keys, mouse buttons and the buttons of the active controller into
+/// `InputActions` with the `InputMap`,
+/// which is read from `INPUT_CONFIG_FILE` if it exists.
+/// No actions are active outside of a match or while typing in the chat.
+pub struct InputMapPlugin;
+
+impl Plugin for InputMapPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let input_map = if Path::new(INPUT_CONFIG_FILE).exists() {
+            InputMap::load(INPUT_CONFIG_FILE).unwrap_or_else(|e| {
+                warn!("{}, using the default key bindings", e);
+                InputMap::default()
+            })
+        } else {
+            InputMap::default()
+        };
+        // actions are determined before any system of the frame uses them
+        app.add_resource(input_map)
+            .init_resource::<ActiveGamepad>()
+            .init_resource::<InputActions>()
+            .add_system_to_stage(stage::PRE_UPDATE, input_actions);
+    }
+}
+
+fn input_actions(
+    screen: Res<ClientScreen>,
+    chat_input: Res<ChatInput>,
+    selection: Res<Selection>,
+    input_map: Res<InputMap>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    gamepad_input: Res<Input<GamepadButton>>,
+    active_gamepad: Res<ActiveGamepad>,
+    mut actions: ResMut<InputActions>,
+    planet_query: Query<&Planet>,
+) {
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+    // keys typed into the lobby screen or the chat are not meant for the game
+    if *screen != ClientScreen::InGame || chat_input.is_typing() {
+        return;
+    }
+
+    let planet_selected = selection.selected().map_or(false, |e| planet_query.get(e).is_ok());
+    let selected_context = if planet_selected {
+        InputContext::Planet
+    } else {
+        InputContext::Moons
+    };
+    let inputs = RawInputs {
+        keys: &keyboard_input,
+        buttons: &mouse_input,
+        pad_buttons: &gamepad_input,
+        gamepad: active_gamepad.0,
+    };
+    for &action in InputAction::ALL.iter() {
+        let context = action.context();
+        if context != InputContext::Global && context != selected_context {
+            continue;
+        }
+        let bindings = input_map.bindings(action);
+        if bindings.iter().any(|b| b.pressed(&inputs)) {
+            actions.pressed.insert(action);
+        }
+        if bindings.iter().any(|b| b.just_pressed(&inputs)) {
+            actions.just_pressed.insert(action);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::net::SocketAddr;
+
+use bevy::{input::Input, prelude::*};
+
+use crate::lobby_screen::*;
+use crate::network::*;
+
+/// Sent when the player chose the server with the given address to connect to.
+pub struct JoinServerEvent {
+    pub addr: SocketAddr,
+}
+
+/// Servers found in the local network, or the reason why searching for them failed.
+pub struct ServerList {
+    browser: Result<ServerBrowser, String>,
+    changed: bool,
+}
+
+impl Default for ServerList {
+    fn default() -> Self {
+        Self {
+            browser: ServerBrowser::new().map_err(|e| e.to_string()),
+            changed: true,
+        }
+    }
+}
+
+struct JoinRoot;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct JoinButton {
+    addr: SocketAddr,
+}
+
+/// This plugin shows the join screen, listing servers discovered in the local network.
+pub struct JoinScreenPlugin;
+
+impl Plugin for JoinScreenPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(ServerList::default())
+            .add_event::<JoinServerEvent>()
+            .add_system(discover_servers)
+            .add_system(join_input)
+            .add_system(join_ui);
+    }
+}
+
+fn discover_servers(screen: Res<ClientScreen>, mut list: ResMut<ServerList>) {
+    if *screen != ClientScreen::Join {
+        return;
+    }
+    // the browser is only unavailable if its socket could not be set up at all
+    let changed = match &mut list.browser {
+        Ok(browser) => browser.poll(),
+        Err(_) => return,
+    };
+    list.changed |= changed;
+}
+
+fn join_input(
+    screen: Res<ClientScreen>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut join_events: ResMut<Events<JoinServerEvent>>,
+    button_query: Query<(&Interaction, &JoinButton)>,
+) {
+    if *screen != ClientScreen::Join || !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+    for (interaction, button) in button_query.iter() {
+        if *interaction == Interaction::Clicked {
+            info!("Joining server {}", button.addr);
+            join_events.send(JoinServerEvent { addr: button.addr });
+        }
+    }
+}
+
+/// System rebuilding the join screen whenever the list of servers changed.
+fn join_ui(
+    commands: &mut Commands,
+    screen: Res<ClientScreen>,
+    materials: Res<LobbyMaterials>,
+    mut list: ResMut<ServerList>,
+    root_query: Query<(Entity, &JoinRoot)>,
+) {
+    let joining = *screen == ClientScreen::Join;
+    if !list.changed && (joining || root_query.iter().next().is_none()) {
+        return;
+    }
+    list.changed = false;
+
+    for (entity, _) in root_query.iter() {
+        commands.despawn_recursive(entity);
+    }
+    if !joining {
+        return;
+    }
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                padding: Rect::all(Val::Px(40.0)),
+                ..Default::default()
+            },
+            material: materials.background.clone(),
+            ..Default::default()
+        })
+        .with(JoinRoot)
+        .with_children(|parent| {
+            spawn_label(parent, &materials, "Moonshot!", 60.0);
+            spawn_label(parent, &materials, "Servers in your network", 36.0);
+
+            let browser = match &list.browser {
+                Ok(browser) => browser,
+                Err(e) => {
+                    let error = format!("Cannot search for servers: {}", e);
+                    spawn_label(parent, &materials, &error, 24.0);
+                    return;
+                }
+            };
+            let servers = browser.servers();
+            if servers.is_empty() {
+                spawn_label(parent, &materials, "searching...", 24.0);
+            }
+            for server in servers {
+                let announcement = &server.announcement;
+                let label = format!(
+                    "{} - {} - {} players, {} open games",
+                    announcement.name,
+                    announcement.map,
+                    announcement.players,
+                    announcement.open_games
+                );
+                if server.is_compatible() {
+                    spawn_button(parent, &materials, &label, JoinButton { addr: server.addr });
+                } else {
+                    let label = format!("{} (incompatible version)", label);
+                    spawn_label(parent, &materials, &label, 24.0);
+                }
+            }
+        });
+}
@@ -1,8 +1,26 @@
 // Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
+pub mod balance;
+pub mod bot;
 pub mod building;
+pub mod camera;
+pub mod chat;
 pub mod combat;
+pub mod command_bar;
 pub mod components;
 pub mod cursor_world_coords;
+pub mod flight;
+pub mod gamepad;
+pub mod input_map;
+pub mod join_screen;
+pub mod lobby_screen;
+pub mod map;
+pub mod minimap;
 pub mod network;
+pub mod picking;
+pub mod players;
+pub mod resource_hud;
+pub mod rng;
+pub mod selection;
+pub mod simulation;
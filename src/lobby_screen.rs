@@ -0,0 +1,439 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::{
+    input::{keyboard::KeyboardInput, ElementState, Input},
+    prelude::*,
+    window::ReceivedCharacter,
+};
+
+use crate::balance::{Balance, LocalBalanceHash};
+use crate::components::PlayerResources;
+use crate::network::*;
+use crate::players::Players;
+
+/// Seconds between two requests for the list of open games.
+const REFRESH_INTERVAL: f32 = 2.0;
+
+/// The screen the client currently shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientScreen {
+    /// Choosing a server to connect to
+    Join,
+    Lobby,
+    InGame,
+}
+
+/// Sent once the server started the match for the game the player is in.
+pub struct MatchStartedEvent {
+    pub game: GameInfo,
+    /// Index of the local player in the game
+    pub player: u8,
+}
+
+/// What the lobby screen currently displays.
+#[derive(Default)]
+pub struct LobbyView {
+    games: Vec<GameInfo>,
+    current_game: Option<GameInfo>,
+    /// Whether the local player declared to be ready in the current game
+    ready: bool,
+    new_game: GameSettings,
+    error: Option<String>,
+    changed: bool,
+}
+
+impl LobbyView {
+    /// Forgets everything learned from the previous server, e.g. after connecting to another one.
+    pub fn reset(&mut self) {
+        *self = LobbyView {
+            new_game: self.new_game.clone(),
+            changed: true,
+            ..Default::default()
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LobbyButton {
+    Refresh,
+    CycleMap,
+    CyclePlayerCount,
+    CycleTeams,
+    ToggleFriendlyFire,
+    Create,
+    Join(GameId),
+    ToggleReady,
+    SetTeam(u8),
+    Leave,
+}
+
+struct LobbyRoot;
+
+/// Materials shared by the menu screens.
+pub struct LobbyMaterials {
+    pub background: Handle<ColorMaterial>,
+    pub button: Handle<ColorMaterial>,
+    pub font: Handle<Font>,
+}
+
+impl FromResources for LobbyMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        let asset_server = resources.get::<AssetServer>().unwrap();
+        LobbyMaterials {
+            background: materials.add(Color::NONE.into()),
+            button: materials.add(Color::rgb(0.25, 0.27, 0.55).into()),
+            font: asset_server.load("fonts/Nunito-Regular.ttf"),
+        }
+    }
+}
+
+/// This plugin shows the lobby screen, where games are created and joined, until a match starts.
+pub struct LobbyScreenPlugin;
+
+impl Plugin for LobbyScreenPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_resource(ClientScreen::Join)
+            .add_resource(LobbyView {
+                changed: true,
+                ..Default::default()
+            })
+            .add_event::<MatchStartedEvent>()
+            .init_resource::<LobbyMaterials>()
+            .add_system(refresh_game_list)
+            .add_system(lobby_messages)
+            .add_system(lobby_input)
+            .add_system(lobby_ui);
+    }
+}
+
+fn send_request(transport: &mut Transport, request: LobbyRequest) {
+    let serialized = bincode::serialize(&ClientMessage::Lobby(request)).unwrap();
+    transport.send(serialized);
+}
+
+fn refresh_game_list(
+    mut timer: Local<Timer>,
+    time: Res<Time>,
+    screen: Res<ClientScreen>,
+    view: Res<LobbyView>,
+    mut transport: ResMut<Transport>,
+) {
+    if *screen != ClientScreen::Lobby || view.current_game.is_some() {
+        return;
+    }
+    // a fresh timer has finished right away, so the list is requested as soon as possible
+    if timer.tick(time.delta_seconds).finished() {
+        *timer = Timer::from_seconds(REFRESH_INTERVAL, false);
+        send_request(&mut transport, LobbyRequest::ListGames);
+    }
+}
+
+#[derive(Default)]
+pub struct LobbyMessageState {
+    lobby_event_reader: EventReader<LobbyResponse>,
+}
+
+fn lobby_messages(
+    mut state: Local<LobbyMessageState>,
+    lobby_events: Res<Events<LobbyResponse>>,
+    mut screen: ResMut<ClientScreen>,
+    mut view: ResMut<LobbyView>,
+    mut match_started_events: ResMut<Events<MatchStartedEvent>>,
+    mut players: ResMut<Players>,
+    mut balance: ResMut<Balance>,
+    local_balance_hash: Res<LocalBalanceHash>,
+    mut resources: ResMut<PlayerResources>,
+) {
+    for response in state.lobby_event_reader.iter(&lobby_events) {
+        view.changed = true;
+        match response {
+            LobbyResponse::GameList(games) => view.games = games.clone(),
+            LobbyResponse::GameUpdated(game) => {
+                if view.current_game.as_ref().map(|g| g.id) != Some(game.id) {
+                    view.ready = false;
+                }
+                view.current_game = Some(game.clone());
+                view.error = None;
+            }
+            LobbyResponse::LeftGame => {
+                view.current_game = None;
+                view.ready = false;
+            }
+            LobbyResponse::Error(reason) => view.error = Some(reason.clone()),
+            LobbyResponse::MatchStarted {
+                game,
+                player,
+                balance: match_balance,
+                balance_hash,
+            } => {
+                info!("Match {} started as player {}", game.id, player);
+                local_balance_hash.check(*balance_hash);
+                *screen = ClientScreen::InGame;
+                *players = Players::new(game, *player);
+                *balance = match_balance.clone();
+                *resources = balance.starting_resources();
+                match_started_events.send(MatchStartedEvent {
+                    game: game.clone(),
+                    player: *player,
+                });
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LobbyInputState {
+    character_event_reader: EventReader<ReceivedCharacter>,
+    keyboard_event_reader: EventReader<KeyboardInput>,
+}
+
+fn lobby_input(
+    mut state: Local<LobbyInputState>,
+    screen: Res<ClientScreen>,
+    character_events: Res<Events<ReceivedCharacter>>,
+    keyboard_events: Res<Events<KeyboardInput>>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut view: ResMut<LobbyView>,
+    mut transport: ResMut<Transport>,
+    button_query: Query<(&Interaction, &LobbyButton)>,
+) {
+    if *screen != ClientScreen::Lobby {
+        return;
+    }
+
+    // typing edits the name of the game to create
+    for event in state.character_event_reader.iter(&character_events) {
+        let editable = view.current_game.is_none() && view.new_game.name.len() < 32;
+        if editable && !event.char.is_control() {
+            view.new_game.name.push(event.char);
+            view.changed = true;
+        }
+    }
+    for event in state.keyboard_event_reader.iter(&keyboard_events) {
+        if event.state == ElementState::Pressed && event.key_code == Some(KeyCode::Back) {
+            view.new_game.name.pop();
+            view.changed = true;
+        }
+    }
+
+    if !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match *button {
+            LobbyButton::Refresh => send_request(&mut transport, LobbyRequest::ListGames),
+            LobbyButton::CycleMap => {
+                let index = MAPS.iter().position(|&map| map == view.new_game.map).unwrap_or(0);
+                view.new_game.map = MAPS[(index + 1) % MAPS.len()].to_string();
+                view.changed = true;
+            }
+            LobbyButton::CyclePlayerCount => {
+                let count = view.new_game.player_count + 1;
+                view.new_game.player_count = if count > MAX_PLAYERS { MIN_PLAYERS } else { count };
+                // there can be no more teams than players
+                if let TeamMode::Teams(teams) = view.new_game.teams {
+                    if teams > view.new_game.player_count {
+                        view.new_game.teams = TeamMode::FreeForAll;
+                    }
+                }
+                view.changed = true;
+            }
+            LobbyButton::CycleTeams => {
+                view.new_game.teams = match view.new_game.teams {
+                    TeamMode::FreeForAll => TeamMode::Teams(2),
+                    TeamMode::Teams(teams) if teams < view.new_game.player_count => {
+                        TeamMode::Teams(teams + 1)
+                    }
+                    TeamMode::Teams(_) => TeamMode::FreeForAll,
+                };
+                view.changed = true;
+            }
+            LobbyButton::ToggleFriendlyFire => {
+                view.new_game.friendly_fire = !view.new_game.friendly_fire;
+                view.changed = true;
+            }
+            LobbyButton::Create => {
+                let settings = view.new_game.clone();
+                send_request(&mut transport, LobbyRequest::CreateGame(settings));
+            }
+            LobbyButton::Join(id) => send_request(&mut transport, LobbyRequest::JoinGame(id)),
+            LobbyButton::ToggleReady => {
+                view.ready = !view.ready;
+                send_request(&mut transport, LobbyRequest::SetReady(view.ready));
+            }
+            LobbyButton::SetTeam(team) => send_request(&mut transport, LobbyRequest::SetTeam(team)),
+            LobbyButton::Leave => send_request(&mut transport, LobbyRequest::LeaveGame),
+        }
+    }
+}
+
+/// System rebuilding the lobby screen whenever its contents changed.
+fn lobby_ui(
+    commands: &mut Commands,
+    screen: Res<ClientScreen>,
+    materials: Res<LobbyMaterials>,
+    mut view: ResMut<LobbyView>,
+    root_query: Query<(Entity, &LobbyRoot)>,
+) {
+    let in_lobby = *screen == ClientScreen::Lobby;
+    if !view.changed && (in_lobby || root_query.iter().next().is_none()) {
+        return;
+    }
+    view.changed = false;
+
+    for (entity, _) in root_query.iter() {
+        commands.despawn_recursive(entity);
+    }
+    if !in_lobby {
+        return;
+    }
+
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                flex_direction: FlexDirection::ColumnReverse,
+                align_items: AlignItems::Center,
+                padding: Rect::all(Val::Px(40.0)),
+                ..Default::default()
+            },
+            material: materials.background.clone(),
+            ..Default::default()
+        })
+        .with(LobbyRoot)
+        .with_children(|parent| {
+            spawn_label(parent, &materials, "Moonshot!", 60.0);
+            if let Some(error) = &view.error {
+                spawn_label(parent, &materials, error, 24.0);
+            }
+
+            match &view.current_game {
+                Some(game) => {
+                    let title = format!("{} ({})", game.settings.name, game.settings.map);
+                    spawn_label(parent, &materials, &title, 36.0);
+                    let rules = format!(
+                        "{}, friendly fire {}",
+                        team_mode_label(game.settings.teams),
+                        if game.settings.friendly_fire { "on" } else { "off" }
+                    );
+                    spawn_label(parent, &materials, &rules, 24.0);
+                    for (index, player) in game.players.iter().enumerate() {
+                        let status = if player.ready { "ready" } else { "not ready" };
+                        let line = match game.settings.teams {
+                            TeamMode::FreeForAll => format!("{} - {}", player.name, status),
+                            TeamMode::Teams(_) => format!(
+                                "{} - team {} - {}",
+                                player.name,
+                                game.team(index) + 1,
+                                status
+                            ),
+                        };
+                        spawn_label(parent, &materials, &line, 24.0);
+                    }
+                    let waiting = format!(
+                        "{} of {} players",
+                        game.players.len(),
+                        game.settings.player_count
+                    );
+                    spawn_label(parent, &materials, &waiting, 24.0);
+                    if let TeamMode::Teams(teams) = game.settings.teams {
+                        for team in 0..teams {
+                            let label = format!("Join team {}", team + 1);
+                            spawn_button(parent, &materials, &label, LobbyButton::SetTeam(team));
+                        }
+                    }
+                    let ready = if view.ready { "Not ready" } else { "Ready" };
+                    spawn_button(parent, &materials, ready, LobbyButton::ToggleReady);
+                    spawn_button(parent, &materials, "Leave", LobbyButton::Leave);
+                }
+                None => {
+                    spawn_label(parent, &materials, "Open games", 36.0);
+                    if view.games.is_empty() {
+                        spawn_label(parent, &materials, "none yet, create one!", 24.0);
+                    }
+                    for game in &view.games {
+                        let label = format!(
+                            "{} - {} - {}/{} players",
+                            game.settings.name,
+                            game.settings.map,
+                            game.players.len(),
+                            game.settings.player_count
+                        );
+                        spawn_button(parent, &materials, &label, LobbyButton::Join(game.id));
+                    }
+                    spawn_button(parent, &materials, "Refresh", LobbyButton::Refresh);
+
+                    spawn_label(parent, &materials, "New game (type to rename)", 36.0);
+                    spawn_label(parent, &materials, &view.new_game.name, 24.0);
+                    let map = format!("Map: {}", view.new_game.map);
+                    spawn_button(parent, &materials, &map, LobbyButton::CycleMap);
+                    let players = format!("Players: {}", view.new_game.player_count);
+                    spawn_button(parent, &materials, &players, LobbyButton::CyclePlayerCount);
+                    let teams = format!("Mode: {}", team_mode_label(view.new_game.teams));
+                    spawn_button(parent, &materials, &teams, LobbyButton::CycleTeams);
+                    let friendly_fire = format!(
+                        "Friendly fire: {}",
+                        if view.new_game.friendly_fire { "on" } else { "off" }
+                    );
+                    let toggle = LobbyButton::ToggleFriendlyFire;
+                    spawn_button(parent, &materials, &friendly_fire, toggle);
+                    spawn_button(parent, &materials, "Create", LobbyButton::Create);
+                }
+            }
+        });
+}
+
+fn team_mode_label(teams: TeamMode) -> String {
+    match teams {
+        TeamMode::FreeForAll => "Free-for-all".to_string(),
+        TeamMode::Teams(count) => format!("{} teams", count),
+    }
+}
+
+pub fn spawn_label(parent: &mut ChildBuilder, materials: &LobbyMaterials, value: &str, size: f32) {
+    parent.spawn(TextBundle {
+        style: Style {
+            margin: Rect::all(Val::Px(5.0)),
+            ..Default::default()
+        },
+        text: Text {
+            value: value.to_string(),
+            font: materials.font.clone(),
+            style: TextStyle {
+                font_size: size,
+                color: Color::WHITE,
+                alignment: TextAlignment::default(),
+            },
+        },
+        ..Default::default()
+    });
+}
+
+pub fn spawn_button<B: Component>(
+    parent: &mut ChildBuilder,
+    materials: &LobbyMaterials,
+    label: &str,
+    button: B,
+) {
+    parent
+        .spawn(ButtonBundle {
+            style: Style {
+                margin: Rect::all(Val::Px(5.0)),
+                padding: Rect::all(Val::Px(8.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.button.clone(),
+            ..Default::default()
+        })
+        .with(button)
+        .with_children(|parent| {
+            spawn_label(parent, materials, label, 24.0);
+        });
+}
@@ -0,0 +1,128 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::balance::Balance;
+use crate::components::{BodyId, Moon, Owner, Planet};
+use crate::picking::Pickable;
+use crate::players::Players;
+
+/// Distance between the home planets of neighbouring players.
+const PLANET_SPACING: f32 = 1000.0;
+
+/// Orbit radius and speed of the moons every home planet starts with.
+const MOON_ORBITS: [(f32, f64); 2] = [(300.0, 1.0), (500.0, 0.5)];
+
+/// Free space around the outermost moon orbits which still belongs to the map.
+const MAP_MARGIN: f32 = 500.0;
+
+/// The area of the world the camera may look at.
+#[derive(Clone, Copy, Debug)]
+pub struct MapBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Default for MapBounds {
+    /// Before a map is spawned, the camera may go anywhere.
+    fn default() -> Self {
+        Self {
+            min: Vec2::splat(f32::MIN),
+            max: Vec2::splat(f32::MAX),
+        }
+    }
+}
+
+impl MapBounds {
+    /// Returns the bounds of the map for the given number of players.
+    pub fn new(player_count: usize) -> Self {
+        let outermost_orbit = MOON_ORBITS.iter().map(|&(radius, _)| radius).fold(0.0, f32::max);
+        let reach = Vec2::splat(outermost_orbit + MAP_MARGIN);
+        let positions = planet_positions(player_count);
+        let min = positions.iter().fold(Vec2::splat(f32::MAX), |min, &pos| min.min(pos));
+        let max = positions.iter().fold(Vec2::splat(f32::MIN), |max, &pos| max.max(pos));
+        Self {
+            min: min - reach,
+            max: max + reach,
+        }
+    }
+
+    /// Returns the point within the bounds which is closest to the given one.
+    pub fn clamp(&self, point: Vec2) -> Vec2 {
+        point.max(self.min).min(self.max)
+    }
+}
+
+/// Returns the positions of the players' home planets, evenly spread on a ring.
+pub fn planet_positions(player_count: usize) -> Vec<Vec2> {
+    if player_count < 2 {
+        return vec![Vec2::splat(0.0); player_count];
+    }
+    let step = 2.0 * PI / player_count as f32;
+    let radius = PLANET_SPACING / 2.0 / (step / 2.0).sin();
+    (0..player_count)
+        .map(|i| {
+            let angle = i as f32 * step;
+            Vec2::new(radius * angle.cos(), radius * angle.sin())
+        })
+        .collect()
+}
+
+/// Spawns a home planet with its moons for every player and sets the `MapBounds`.
+///
+/// Bodies are numbered in the same order on every client, so that player actions can refer to
+/// them by their `BodyId`.
+pub fn spawn_map(
+    commands: &mut Commands,
+    texture_atlas_handle: Handle<TextureAtlas>,
+    players: &Players,
+    balance: &Balance,
+) {
+    commands.insert_resource(MapBounds::new(players.len()));
+
+    let mut next_id = 0;
+    for (player, position) in planet_positions(players.len()).into_iter().enumerate() {
+        let owner = Owner(player as u8);
+        commands
+            .spawn(SpriteSheetBundle {
+                sprite: TextureAtlasSprite {
+                    color: players.color(owner.0),
+                    index: balance.sprites.planet,
+                },
+                texture_atlas: texture_atlas_handle.clone(),
+                transform: Transform::from_translation(position.extend(0.0)),
+                ..Default::default()
+            })
+            .with(Planet::default())
+            .with(BodyId(next_id))
+            .with(owner)
+            .with(Pickable::default());
+        next_id += 1;
+
+        let planet = commands.current_entity().unwrap();
+        for &(orbit_radius, speed) in MOON_ORBITS.iter() {
+            commands
+                .spawn(SpriteSheetBundle {
+                    sprite: TextureAtlasSprite::new(balance.sprites.moon),
+                    texture_atlas: texture_atlas_handle.clone(),
+                    transform: Transform::from_scale(Vec3::splat(0.5)),
+                    ..Default::default()
+                })
+                .with(Moon {
+                    orbit_radius,
+                    speed,
+                    building: None,
+                })
+                .with(BodyId(next_id))
+                .with(owner)
+                .with(Pickable::default());
+            next_id += 1;
+
+            let moon = commands.current_entity().unwrap();
+            commands.push_children(planet, &[moon]);
+        }
+    }
+}
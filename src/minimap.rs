@@ -0,0 +1,335 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+
+use bevy::{input::Input, prelude::*, render::camera::Camera, ui::camera::UI_CAMERA};
+
+use crate::camera::CameraGoal;
+use crate::components::{Moon, Owner, Planet};
+use crate::lobby_screen::ClientScreen;
+use crate::map::MapBounds;
+use crate::network::{ClientMessage, Transport};
+use crate::picking::Picking;
+use crate::players::Players;
+
+/// Width and height of the minimap in pixels.
+pub const MINIMAP_SIZE: f32 = 200.0;
+
+/// Distance of the minimap from the bottom right corner of the window.
+pub const MINIMAP_MARGIN: f32 = 10.0;
+
+/// Sizes of the markers on the minimap in pixels.
+const PLANET_MARKER_SIZE: f32 = 10.0;
+const MOON_MARKER_SIZE: f32 = 6.0;
+const ROCKET_MARKER_SIZE: f32 = 3.0;
+const ORBIT_DOT_SIZE: f32 = 2.0;
+
+/// Number of dots drawing the orbit of a moon.
+const ORBIT_DOTS: usize = 24;
+
+pub struct MinimapMaterials {
+    background: Handle<ColorMaterial>,
+    viewport: Handle<ColorMaterial>,
+    orbit: Handle<ColorMaterial>,
+}
+
+impl FromResources for MinimapMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        MinimapMaterials {
+            background: materials.add(Color::rgba(0.05, 0.06, 0.15, 0.8).into()),
+            viewport: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.15).into()),
+            orbit: materials.add(Color::rgba(1.0, 1.0, 1.0, 0.3).into()),
+        }
+    }
+}
+
+struct MinimapRoot;
+
+/// The part of the minimap the game camera currently shows.
+struct MinimapViewport;
+
+/// A marker or orbit dot on the minimap.
+struct MinimapNode;
+
+/// Maps between world coordinates and pixels on the minimap, measured from its bottom left.
+struct MinimapProjection {
+    min: Vec2,
+    scale: f32,
+    offset: Vec2,
+}
+
+impl MinimapProjection {
+    fn new(bounds: &MapBounds) -> Self {
+        let extent = bounds.max - bounds.min;
+        let scale = MINIMAP_SIZE / extent.x.max(extent.y);
+        Self {
+            min: bounds.min,
+            scale,
+            // the shorter side of the map is centered
+            offset: (Vec2::splat(MINIMAP_SIZE) - extent * scale) / 2.0,
+        }
+    }
+
+    fn to_minimap(&self, world: Vec2) -> Vec2 {
+        (world - self.min) * self.scale + self.offset
+    }
+
+    fn to_world(&self, minimap: Vec2) -> Vec2 {
+        (minimap - self.offset) / self.scale + self.min
+    }
+}
+
+/// Returns the style of a node with the given center and size on the minimap.
+fn node_style(center: Vec2, size: Vec2) -> Style {
+    Style {
+        position_type: PositionType::Absolute,
+        position: Rect {
+            left: Val::Px(center.x - size.x / 2.0),
+            bottom: Val::Px(center.y - size.y / 2.0),
+            ..Default::default()
+        },
+        size: Size::new(Val::Px(size.x), Val::Px(size.y)),
+        ..Default::default()
+    }
+}
+
+/// This plugin shows a minimap of the star system in the bottom right corner during a match.
+///
+/// Planets, moons and rockets are shown in the color of their owner, together with the moon
+/// orbits and the part of the map the camera currently shows.
+/// Clicking or dragging on the minimap moves the camera there, clicking while holding Alt
+/// pings the map.
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<MinimapMaterials>()
+            .add_system(minimap_ui)
+            .add_system(minimap_viewport)
+            .add_system(minimap_input);
+    }
+}
+
+#[derive(Default)]
+pub struct MinimapUiState {
+    root: Option<Entity>,
+    /// Nodes showing a planet, moon or rocket, followed by the dots of a moon's orbit
+    nodes: HashMap<Entity, Vec<Entity>>,
+}
+
+/// System keeping the minimap in sync with the planets, moons and rockets in the world.
+fn minimap_ui(
+    commands: &mut Commands,
+    mut state: Local<MinimapUiState>,
+    screen: Res<ClientScreen>,
+    bounds: Res<MapBounds>,
+    players: Res<Players>,
+    materials: Res<MinimapMaterials>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    body_query: Query<(
+        Entity,
+        &Owner,
+        &GlobalTransform,
+        &Transform,
+        Option<&Planet>,
+        Option<&Moon>,
+    )>,
+    mut node_query: Query<(&MinimapNode, Mut<Style>, &Handle<ColorMaterial>)>,
+) {
+    if *screen != ClientScreen::InGame {
+        if let Some(root) = state.root.take() {
+            commands.despawn_recursive(root);
+            state.nodes.clear();
+        }
+        return;
+    }
+    let root = match state.root {
+        Some(root) => root,
+        None => {
+            let root = commands
+                .spawn(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            right: Val::Px(MINIMAP_MARGIN),
+                            bottom: Val::Px(MINIMAP_MARGIN),
+                            ..Default::default()
+                        },
+                        size: Size::new(Val::Px(MINIMAP_SIZE), Val::Px(MINIMAP_SIZE)),
+                        ..Default::default()
+                    },
+                    material: materials.background.clone(),
+                    ..Default::default()
+                })
+                .with(MinimapRoot)
+                .with_children(|parent| {
+                    parent
+                        .spawn(NodeBundle {
+                            material: materials.viewport.clone(),
+                            ..Default::default()
+                        })
+                        .with(MinimapViewport);
+                })
+                .current_entity()
+                .unwrap();
+            state.root = Some(root);
+            root
+        }
+    };
+
+    let projection = MinimapProjection::new(&bounds);
+    let mut seen = HashSet::new();
+    for (entity, owner, global, local, planet, moon) in body_query.iter() {
+        seen.insert(entity);
+        let size = match (planet, moon) {
+            (Some(_), _) => PLANET_MARKER_SIZE,
+            (_, Some(_)) => MOON_MARKER_SIZE,
+            _ => ROCKET_MARKER_SIZE,
+        };
+        let position = projection.to_minimap(global.translation.truncate());
+        let color = players.color(owner.0);
+
+        if !state.nodes.contains_key(&entity) {
+            let mut nodes = vec![];
+            commands
+                .spawn(NodeBundle {
+                    style: node_style(position, Vec2::splat(size)),
+                    material: color_materials.add(color.into()),
+                    ..Default::default()
+                })
+                .with(MinimapNode);
+            nodes.push(commands.current_entity().unwrap());
+            if moon.is_some() {
+                for _ in 0..ORBIT_DOTS {
+                    commands
+                        .spawn(NodeBundle {
+                            material: materials.orbit.clone(),
+                            ..Default::default()
+                        })
+                        .with(MinimapNode);
+                    nodes.push(commands.current_entity().unwrap());
+                }
+            }
+            commands.push_children(root, &nodes);
+            state.nodes.insert(entity, nodes);
+            continue;
+        }
+        let nodes = &state.nodes[&entity];
+
+        // the first node is the marker itself, whose color changes e.g. once a player is out
+        if let Ok((_, mut style, material)) = node_query.get_mut(nodes[0]) {
+            *style = node_style(position, Vec2::splat(size));
+            if color_materials.get(material).map(|m| m.color) != Some(color) {
+                if let Some(material) = color_materials.get_mut(material) {
+                    material.color = color;
+                }
+            }
+        }
+        // moons are children of their planet, which is at the center of the orbit
+        if let Some(moon) = moon {
+            let center = global.translation.truncate() - local.translation.truncate();
+            for (i, &dot) in nodes.iter().skip(1).enumerate() {
+                let angle = i as f32 * 2.0 * PI / ORBIT_DOTS as f32;
+                let on_orbit = center + moon.orbit_radius * Vec2::new(angle.cos(), angle.sin());
+                if let Ok((_, mut style, _)) = node_query.get_mut(dot) {
+                    let size = Vec2::splat(ORBIT_DOT_SIZE);
+                    *style = node_style(projection.to_minimap(on_orbit), size);
+                }
+            }
+        }
+    }
+
+    // remove the nodes of rockets which hit or flew out of range
+    let gone: Vec<Entity> = state.nodes.keys().filter(|e| !seen.contains(*e)).copied().collect();
+    for entity in gone {
+        for node in state.nodes.remove(&entity).unwrap() {
+            commands.despawn(node);
+        }
+    }
+}
+
+/// System showing the part of the map the game camera currently shows on the minimap.
+fn minimap_viewport(
+    bounds: Res<MapBounds>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &Transform)>,
+    mut viewport_query: Query<(&MinimapViewport, Mut<Style>)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let window_size = Vec2::new(window.width() as f32, window.height() as f32);
+    let projection = MinimapProjection::new(&bounds);
+
+    for (camera, trans) in camera_query.iter() {
+        if camera.name == Some(UI_CAMERA.to_string()) {
+            continue;
+        }
+        let half_size = window_size * trans.scale.x / 2.0;
+        let center = trans.translation.truncate();
+        // the viewport is cut off at the edges of the minimap
+        let clamp = |p: Vec2| p.max(Vec2::splat(0.0)).min(Vec2::splat(MINIMAP_SIZE));
+        let min = clamp(projection.to_minimap(center - half_size));
+        let max = clamp(projection.to_minimap(center + half_size));
+        for (_, mut style) in viewport_query.iter_mut() {
+            *style = node_style((min + max) / 2.0, max - min);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MinimapInputState {
+    cursor_event_reader: EventReader<CursorMoved>,
+    /// Last known cursor position in the window
+    window_position: Option<Vec2>,
+}
+
+/// System moving the camera or pinging the map when the minimap is clicked.
+fn minimap_input(
+    mut state: Local<MinimapInputState>,
+    screen: Res<ClientScreen>,
+    bounds: Res<MapBounds>,
+    windows: Res<Windows>,
+    cursor_inputs: Res<Events<CursorMoved>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut picking: ResMut<Picking>,
+    mut goal: ResMut<CameraGoal>,
+    mut transport: ResMut<Transport>,
+) {
+    if let Some(event) = state.cursor_event_reader.iter(&cursor_inputs).last() {
+        state.window_position = Some(event.position);
+    }
+    let (window_position, window) = match (state.window_position, windows.get_primary()) {
+        (Some(position), Some(window)) if *screen == ClientScreen::InGame => (position, window),
+        _ => return,
+    };
+
+    // the minimap is anchored to the bottom right corner of the window
+    let origin = Vec2::new(window.width() as f32 - MINIMAP_MARGIN - MINIMAP_SIZE, MINIMAP_MARGIN);
+    let on_minimap = window_position - origin;
+    let inside = on_minimap.x >= 0.0
+        && on_minimap.y >= 0.0
+        && on_minimap.x <= MINIMAP_SIZE
+        && on_minimap.y <= MINIMAP_SIZE;
+    if !inside {
+        return;
+    }
+    // sprites in the world below the minimap cannot be clicked
+    picking.block();
+
+    let world = MinimapProjection::new(&bounds).to_world(on_minimap);
+    let alt = keyboard_input.pressed(KeyCode::LAlt) || keyboard_input.pressed(KeyCode::RAlt);
+    if alt {
+        if mouse_input.just_pressed(MouseButton::Left) {
+            let message = ClientMessage::MapPing { position: world };
+            transport.send(bincode::serialize(&message).unwrap());
+        }
+    } else if mouse_input.pressed(MouseButton::Left) {
+        goal.position = world;
+    }
+}
@@ -0,0 +1,30 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Longest chat message the server relays (in characters).
+pub const MAX_CHAT_LENGTH: usize = 200;
+
+/// Who gets to see a chat message or map ping.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChatChannel {
+    All,
+    Team,
+}
+
+/// A chat message relayed by the server.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ChatMessage {
+    pub player: u8,
+    pub channel: ChatChannel,
+    pub text: String,
+}
+
+/// A marker a player placed on the map for their team.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct MapPing {
+    pub player: u8,
+    pub position: Vec2,
+}
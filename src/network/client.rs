@@ -0,0 +1,217 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use crate::balance::Balance;
+use crate::combat::launch_power;
+use crate::components::{BodyId, Moon, Owner, Planet, Rocket};
+use crate::players::Players;
+use super::*;
+
+/// Turns received from the server which have not been executed yet, indexed by frame.
+#[derive(Default)]
+pub struct TurnBuffer {
+    turns: BTreeMap<u32, ServerTurn>,
+}
+
+/// System periodically sending pings to the server.
+pub fn send_pings(mut state: Local<PingState>, time: Res<Time>, mut transport: ResMut<Transport>) {
+    state.send(&time, &mut transport, None, ClientMessage::Ping);
+}
+
+/// System telling the server once the match is decided, so that it can end the match.
+pub fn report_match_over(
+    mut reported: Local<bool>,
+    players: Res<Players>,
+    mut transport: ResMut<Transport>,
+) {
+    let over = players.is_match_over();
+    if over && !*reported {
+        transport.send(bincode::serialize(&ClientMessage::MatchOver).unwrap());
+    }
+    *reported = over;
+}
+
+#[derive(Default)]
+pub struct ClientMessageState {
+    network_event_reader: EventReader<NetworkSimulationEvent>,
+}
+
+pub fn handle_messages(
+    mut state: Local<ClientMessageState>,
+    time: Res<Time>,
+    network_events: Res<Events<NetworkSimulationEvent>>,
+    mut transport: ResMut<Transport>,
+    mut sim_time: ResMut<NetworkSimulationTime>,
+    mut stats: ResMut<ConnectionStats>,
+    mut turn_buffer: ResMut<TurnBuffer>,
+    mut pending: ResMut<PendingActions>,
+    mut time_control: ResMut<TimeControl>,
+    mut lobby_events: ResMut<Events<LobbyResponse>>,
+    mut chat_events: ResMut<Events<ChatMessage>>,
+    mut map_ping_events: ResMut<Events<MapPing>>,
+) {
+    for event in state.network_event_reader.iter(&network_events) {
+        let payload = match event {
+            NetworkSimulationEvent::Message(_, payload) => payload,
+            NetworkSimulationEvent::Connect(addr) => {
+                info!("Connected to server {}", addr);
+                continue;
+            }
+            NetworkSimulationEvent::Disconnect(addr) => {
+                error!("Lost connection to server {}", addr);
+                continue;
+            }
+        };
+
+        let message = match bincode::deserialize::<ServerMessage>(payload) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Received malformed message from server: {}", e);
+                continue;
+            }
+        };
+        trace!("Received msg: {:?}", message);
+
+        match message {
+            ServerMessage::Turn(turn) => {
+                turn_buffer.turns.insert(turn.frame, turn);
+            }
+            ServerMessage::ActionScheduled { sequence, frame } => {
+                pending.scheduled(sequence, frame);
+            }
+            ServerMessage::ActionRejected { sequence } => {
+                info!("Server rejected action {}", sequence);
+                pending.rejected(sequence);
+            }
+            ServerMessage::Resumed { player } => {
+                let frame = sim_time.frame_number();
+                let resume = IssuedAction {
+                    player,
+                    action: PlayerAction::Resume,
+                };
+                time_control.apply(&resume, frame, &mut sim_time);
+                // resuming is not part of any turn, so it counts as executed right away
+                pending.executed_until(frame);
+            }
+            ServerMessage::Chat(message) => {
+                chat_events.send(message);
+            }
+            ServerMessage::MapPing(ping) => {
+                map_ping_events.send(ping);
+            }
+            ServerMessage::Ping(ping) => {
+                let pong = ping.answer(time.seconds_since_startup, &sim_time);
+                let serialized = bincode::serialize(&ClientMessage::Pong(pong)).unwrap();
+                transport.send_with(None, Delivery::Unreliable, serialized);
+            }
+            ServerMessage::Pong(pong) => {
+                stats.update(&pong, time.seconds_since_startup);
+                synchronize_frames(&mut sim_time, &mut stats, &pong);
+            }
+            ServerMessage::Lobby(response) => {
+                lobby_events.send(response);
+            }
+        }
+    }
+}
+
+/// System executing the buffered turns once the simulation reaches their frame.
+pub fn execute_turns(
+    commands: &mut Commands,
+    mut sim_time: ResMut<NetworkSimulationTime>,
+    mut time_control: ResMut<TimeControl>,
+    players: Res<Players>,
+    balance: Res<Balance>,
+    mut turn_buffer: ResMut<TurnBuffer>,
+    mut pending: ResMut<PendingActions>,
+    mut moon_query: Query<(&BodyId, &Owner, Mut<Moon>, Mut<TextureAtlasSprite>)>,
+    mut planet_query: Query<(&BodyId, &Owner, Mut<Planet>)>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+) {
+    while let Some(&frame) = turn_buffer.turns.keys().next() {
+        // a pause sets the frame number back to its own frame, stopping before the next turn
+        let current_frame = sim_time.frame_number();
+        if frame > current_frame {
+            break;
+        }
+        if frame + sim_time.frame_lag() < current_frame {
+            warn!("Turn for frame {} arrived late, now at frame {}", frame, current_frame);
+        }
+
+        let turn = turn_buffer.turns.remove(&frame).unwrap();
+        for issued in turn.actions {
+            // the server applies these as well, so they count for eliminated players too
+            if TimeControl::is_time_action(&issued.action) {
+                time_control.apply(&issued, frame, &mut sim_time);
+                continue;
+            }
+            // players which lost their planet have no say anymore
+            if players.is_eliminated(issued.player) {
+                continue;
+            }
+            execute_action(
+                commands,
+                issued,
+                frame,
+                &players,
+                &balance,
+                &mut moon_query,
+                &mut planet_query,
+                &texture_atlases,
+            );
+        }
+        pending.executed_until(frame);
+    }
+}
+
+fn execute_action(
+    commands: &mut Commands,
+    issued: IssuedAction,
+    frame: u32,
+    players: &Players,
+    balance: &Balance,
+    moon_query: &mut Query<(&BodyId, &Owner, Mut<Moon>, Mut<TextureAtlasSprite>)>,
+    planet_query: &mut Query<(&BodyId, &Owner, Mut<Planet>)>,
+    texture_atlases: &Assets<TextureAtlas>,
+) {
+    match issued.action {
+        PlayerAction::Build { building, moon } => {
+            for (id, owner, mut moon_data, mut sprite) in moon_query.iter_mut() {
+                if id.0 == moon && owner.0 == issued.player {
+                    sprite.index = balance.building_moon_texture_index(building);
+                    moon_data.building = Some(building);
+                }
+            }
+        },
+        PlayerAction::ChangeAura { aura, planet } => {
+            for (id, owner, mut planet_data) in planet_query.iter_mut() {
+                if id.0 == planet && owner.0 == issued.player {
+                    planet_data.current_aura = aura;
+                }
+            }
+        }
+        PlayerAction::ShootRocket { pos, dir, power } => {
+            let angle = dir.y.atan2(dir.x);
+            commands.spawn(SpriteSheetBundle {
+                sprite: TextureAtlasSprite {
+                    color: players.color(issued.player),
+                    index: balance.sprites.rocket,
+                },
+                texture_atlas: texture_atlases.get_handle("SPRITE_SHEET"),
+                transform: Transform {
+                    translation: pos.extend(0.0),
+                    rotation: Quat::from_rotation_z(angle),
+                    scale: Vec3::splat(0.25),
+                },
+                ..Default::default()
+            })
+            .with(Rocket::new(pos, balance.rocket_speed * launch_power(power) * dir, frame))
+            .with(Owner(issued.player));
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+use crate::rng::Rng;
+use super::{Delivery, NetworkBackend, NetworkSimulationEvent};
+
+/// Extra delay of a lost reliable message, standing in for its retransmission.
+const RESEND_PENALTY: Duration = Duration::from_millis(200);
+
+/// Bad network conditions which are simulated for outgoing messages.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// Delay added to every message
+    pub latency: Duration,
+    /// Maximum random deviation from `latency`, in both directions
+    pub jitter: Duration,
+    /// Probability of a message getting lost
+    pub loss: f32,
+    /// Probability of a message arriving twice
+    pub duplication: f32,
+    /// Probability of a message being held back until after the ones sent after it
+    pub reordering: f32,
+    /// Seed for the random decisions, so that runs can be reproduced
+    pub seed: u64,
+}
+
+impl NetworkConditions {
+    /// Parses the conditions from command line arguments, i.e. any of `--latency <ms>`,
+    /// `--jitter <ms>`, `--loss <probability>`, `--duplication <probability>`,
+    /// `--reordering <probability>` and `--seed <number>`.
+    /// Returns `None` if no condition was given.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut conditions = NetworkConditions::default();
+        let mut any = false;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for argument {}", arg))
+            };
+            match arg.as_str() {
+                "--latency" => conditions.latency = parse_millis(&value()?)?,
+                "--jitter" => conditions.jitter = parse_millis(&value()?)?,
+                "--loss" => conditions.loss = parse_probability(&value()?)?,
+                "--duplication" => conditions.duplication = parse_probability(&value()?)?,
+                "--reordering" => conditions.reordering = parse_probability(&value()?)?,
+                "--seed" => {
+                    let value = value()?;
+                    conditions.seed = value
+                        .parse()
+                        .map_err(|_| format!("Invalid seed: {}", value))?;
+                }
+                _ => continue,
+            }
+            any = true;
+        }
+        Ok(if any { Some(conditions) } else { None })
+    }
+}
+
+fn parse_millis(value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| format!("Invalid number of milliseconds: {}", value))
+}
+
+fn parse_probability(value: &str) -> Result<f32, String> {
+    match value.parse() {
+        Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+        _ => Err(format!("Invalid probability: {}", value)),
+    }
+}
+
+#[derive(Clone)]
+struct DelayedMessage {
+    destination: SocketAddr,
+    payload: Vec<u8>,
+    delivery: Delivery,
+}
+
+/// Decides what happens to outgoing packets under the given network conditions and holds them
+/// back until they are due.
+pub(super) struct Conditioner<T> {
+    conditions: NetworkConditions,
+    rng: Rng,
+    /// Packets waiting for their delay to pass, ordered by due time and then by send order
+    delayed: BTreeMap<(Instant, u64), T>,
+    next_index: u64,
+}
+
+impl<T: Clone> Conditioner<T> {
+    pub(super) fn new(conditions: NetworkConditions) -> Self {
+        info!("Simulating network conditions: {:?}", conditions);
+        Self {
+            rng: Rng::new(conditions.seed),
+            conditions,
+            delayed: BTreeMap::new(),
+            next_index: 0,
+        }
+    }
+
+    fn random_delay(&mut self) -> Duration {
+        let jitter = self.conditions.jitter.as_secs_f32() * (2.0 * self.rng.next_f32() - 1.0);
+        let delay = self.conditions.latency.as_secs_f32() + jitter;
+        Duration::from_secs_f32(delay.max(0.0))
+    }
+
+    fn enqueue(&mut self, due: Instant, packet: T) {
+        self.delayed.insert((due, self.next_index), packet);
+        self.next_index += 1;
+    }
+
+    /// Delays the packet, which may also get lost, duplicated or overtaken by later ones.
+    /// Packets which are `reliable` are never dropped or duplicated, their loss instead shows up
+    /// as the additional delay a retransmission would cause.
+    pub(super) fn submit(&mut self, packet: T, reliable: bool) {
+        let now = Instant::now();
+        let mut delay = self.random_delay();
+        if self.rng.chance(self.conditions.loss) {
+            if !reliable {
+                return;
+            }
+            delay += self.conditions.latency + RESEND_PENALTY;
+        }
+        if self.rng.chance(self.conditions.reordering) {
+            // hold the packet back long enough for later ones to overtake it
+            delay += self.conditions.latency + self.conditions.jitter + Duration::from_millis(50);
+        }
+
+        let copies = if !reliable && self.rng.chance(self.conditions.duplication) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            self.enqueue(now + delay, packet.clone());
+            delay = self.random_delay();
+        }
+    }
+
+    /// Returns the packets whose delay has passed, in the order they are due.
+    pub(super) fn due(&mut self) -> Vec<T> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        while let Some(&key) = self.delayed.keys().next() {
+            if key.0 > now {
+                break;
+            }
+            due.push(self.delayed.remove(&key).unwrap());
+        }
+        due
+    }
+}
+
+/// Wraps a network backend and subjects its outgoing messages to the given network conditions.
+///
+/// Only outgoing messages are affected, so both client and server need to use it to slow down
+/// both directions. Reliable messages are never dropped or duplicated, their loss instead shows
+/// up as the additional delay a retransmission would cause.
+/// As this happens above the backend, the reliability layer of the UDP backend never sees a lost
+/// datagram, which is why that backend simulates the conditions on its own datagrams instead (see
+/// `UdpBackend::with_conditions`).
+pub struct ConditionedBackend<B> {
+    inner: B,
+    conditioner: Conditioner<DelayedMessage>,
+}
+
+impl<B: NetworkBackend> ConditionedBackend<B> {
+    pub fn new(inner: B, conditions: NetworkConditions) -> Self {
+        Self {
+            inner,
+            conditioner: Conditioner::new(conditions),
+        }
+    }
+}
+
+impl<B: NetworkBackend> NetworkBackend for ConditionedBackend<B> {
+    fn send(
+        &mut self,
+        destination: SocketAddr,
+        payload: &[u8],
+        delivery: Delivery,
+    ) -> io::Result<()> {
+        let message = DelayedMessage {
+            destination,
+            payload: payload.to_vec(),
+            delivery,
+        };
+        self.conditioner.submit(message, delivery == Delivery::Reliable);
+        Ok(())
+    }
+
+    fn peers(&self) -> Vec<SocketAddr> {
+        self.inner.peers()
+    }
+
+    fn poll(&mut self) -> Vec<NetworkSimulationEvent> {
+        for message in self.conditioner.due() {
+            let result = self
+                .inner
+                .send(message.destination, &message.payload, message.delivery);
+            if let Err(e) = result {
+                error!("Failed to send delayed message to {}: {}", message.destination, e);
+            }
+        }
+
+        self.inner.poll()
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use super::{ConnectionStats, IssuedAction, ServerTurn};
+
+/// Lower bound for the input delay (in simulation frames).
+pub const MIN_INPUT_DELAY: u32 = 2;
+
+/// Upper bound for the input delay (in simulation frames).
+pub const MAX_INPUT_DELAY: u32 = 30;
+
+/// Input delay used until round-trip times have been measured (in simulation frames).
+pub const INITIAL_INPUT_DELAY: u32 = 6;
+
+/// Seconds between two steps of the input delay towards its target.
+const DELAY_ADJUST_INTERVAL: f32 = 0.5;
+
+/// Decides on which simulation frame player actions get executed and collects them into turns.
+///
+/// Actions are scheduled `delay` frames into the future, where the delay follows the latency of
+/// the slowest player, so that every client receives the turn before it has to execute it.
+pub struct TurnScheduler {
+    /// Current input delay (in simulation frames)
+    delay: u32,
+    /// Last frame for which the turn has already been sent out
+    last_closed_frame: u32,
+    /// Actions already scheduled for frames which are not yet closed
+    scheduled: BTreeMap<u32, Vec<IssuedAction>>,
+    adjust_timer: Timer,
+}
+
+impl Default for TurnScheduler {
+    fn default() -> Self {
+        Self {
+            delay: INITIAL_INPUT_DELAY,
+            last_closed_frame: 0,
+            scheduled: BTreeMap::new(),
+            adjust_timer: Timer::from_seconds(DELAY_ADJUST_INTERVAL, true),
+        }
+    }
+}
+
+impl TurnScheduler {
+    /// Returns the current input delay (in simulation frames).
+    pub fn delay(&self) -> u32 {
+        self.delay
+    }
+
+    /// Schedules the action for a future frame and returns the number of that frame.
+    pub fn schedule(&mut self, action: IssuedAction, current_frame: u32) -> u32 {
+        let frame = (current_frame + self.delay).max(self.last_closed_frame + 1);
+        self.scheduled.entry(frame).or_default().push(action);
+        frame
+    }
+
+    /// Moves the input delay one step towards the one required by the connection statistics.
+    /// The delay changes gradually so that turns keep a steady rhythm on the clients.
+    pub fn adjust_delay<'a>(
+        &mut self,
+        delta_seconds: f32,
+        per_frame_duration: f32,
+        stats: impl Iterator<Item = &'a ConnectionStats>,
+    ) {
+        if !self.adjust_timer.tick(delta_seconds).just_finished() {
+            return;
+        }
+
+        let target = required_delay(per_frame_duration, stats);
+        if target > self.delay {
+            self.delay += 1;
+        } else if target < self.delay {
+            self.delay -= 1;
+        }
+    }
+
+    /// Closes all frames which can no longer receive new actions and returns their turns.
+    pub fn close_turns(&mut self, current_frame: u32) -> Vec<ServerTurn> {
+        let frontier = current_frame + self.delay;
+        let mut turns = Vec::new();
+        for frame in (self.last_closed_frame + 1)..=frontier {
+            let actions = self.scheduled.remove(&frame).unwrap_or_default();
+            turns.push(ServerTurn::new(frame, actions));
+        }
+        self.last_closed_frame = self.last_closed_frame.max(frontier);
+        turns
+    }
+}
+
+/// Computes the input delay needed for turns to reach every client before they are executed.
+fn required_delay<'a>(
+    per_frame_duration: f32,
+    stats: impl Iterator<Item = &'a ConnectionStats>,
+) -> u32 {
+    let mut worst_latency: Option<f32> = None;
+    for stats in stats {
+        if stats.samples == 0 {
+            return INITIAL_INPUT_DELAY;
+        }
+        // one-way latency plus a safety margin for jitter
+        let latency = stats.rtt / 2.0 + 2.0 * stats.jitter;
+        worst_latency = Some(worst_latency.map_or(latency, |w| w.max(latency)));
+    }
+
+    match worst_latency {
+        Some(latency) => {
+            let frames = (latency / per_frame_duration).ceil() as u32 + 1;
+            frames.max(MIN_INPUT_DELAY).min(MAX_INPUT_DELAY)
+        }
+        None => INITIAL_INPUT_DELAY,
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Port servers listen on for discovery queries broadcast in the local network.
+pub const DISCOVERY_PORT: u16 = 7778;
+
+const QUERY_INTERVAL: Duration = Duration::from_secs(1);
+/// Servers which have not answered for this long are removed from the list.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(4);
+const MAX_DATAGRAM_SIZE: usize = 1024;
+
+#[derive(Deserialize, Serialize, Debug)]
+pub enum DiscoveryPacket {
+    Query,
+    Announcement(ServerAnnouncement),
+}
+
+/// Description of a server, sent in answer to a discovery query.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct ServerAnnouncement {
+    pub name: String,
+    /// Map of the open game with the most players, or the default map if there is none
+    pub map: String,
+    /// Number of players connected to the server
+    pub players: u32,
+    pub open_games: u32,
+    pub protocol_version: u32,
+    /// Port the server accepts game connections on
+    pub port: u16,
+}
+
+/// A server which answered a discovery query.
+#[derive(Clone, Debug)]
+pub struct DiscoveredServer {
+    /// Address game connections to the server are made to
+    pub addr: SocketAddr,
+    pub announcement: ServerAnnouncement,
+    last_seen: Instant,
+}
+
+impl DiscoveredServer {
+    /// Returns whether the server speaks the same protocol version as this client.
+    pub fn is_compatible(&self) -> bool {
+        self.announcement.protocol_version == super::PROTOCOL_VERSION
+    }
+}
+
+/// Finds servers in the local network by regularly broadcasting discovery queries.
+pub struct ServerBrowser {
+    socket: UdpSocket,
+    servers: HashMap<SocketAddr, DiscoveredServer>,
+    last_query: Option<Instant>,
+}
+
+impl ServerBrowser {
+    pub fn new() -> io::Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            servers: HashMap::new(),
+            last_query: None,
+        })
+    }
+
+    /// Broadcasts a query if the last one is long enough ago and handles all announcements.
+    /// Returns whether the list of servers changed.
+    ///
+    /// Errors are only logged, as the network may well become reachable later on.
+    pub fn poll(&mut self) -> bool {
+        let now = Instant::now();
+        if self.last_query.map_or(true, |last| now - last >= QUERY_INTERVAL) {
+            let query = bincode::serialize(&DiscoveryPacket::Query).unwrap();
+            if let Err(e) = self.socket.send_to(&query, (Ipv4Addr::BROADCAST, DISCOVERY_PORT)) {
+                warn!("Failed to broadcast discovery query: {}", e);
+            }
+            self.last_query = Some(now);
+        }
+
+        let mut changed = false;
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        loop {
+            let (length, source) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Failed to receive discovery announcement: {}", e);
+                    break;
+                }
+            };
+            let announcement = match bincode::deserialize(&buffer[..length]) {
+                Ok(DiscoveryPacket::Announcement(announcement)) => announcement,
+                Ok(DiscoveryPacket::Query) => continue,
+                Err(e) => {
+                    debug!("Received malformed discovery packet from {}: {}", source, e);
+                    continue;
+                }
+            };
+            let addr = SocketAddr::new(source.ip(), announcement.port);
+            changed |= self
+                .servers
+                .get(&addr)
+                .map_or(true, |known| known.announcement != announcement);
+            self.servers.insert(
+                addr,
+                DiscoveredServer {
+                    addr,
+                    announcement,
+                    last_seen: now,
+                },
+            );
+        }
+
+        let count = self.servers.len();
+        self.servers.retain(|_, server| now - server.last_seen < SERVER_TIMEOUT);
+        changed || self.servers.len() != count
+    }
+
+    /// Returns all currently known servers, sorted by name.
+    pub fn servers(&self) -> Vec<&DiscoveredServer> {
+        let mut servers: Vec<_> = self.servers.values().collect();
+        servers.sort_by(|a, b| a.announcement.name.cmp(&b.announcement.name));
+        servers
+    }
+}
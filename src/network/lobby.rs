@@ -0,0 +1,128 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use serde::{Deserialize, Serialize};
+
+use crate::balance::Balance;
+
+pub type GameId = u32;
+
+/// Maps which can be chosen when creating a game.
+pub const MAPS: &[&str] = &["Planet Ring"];
+
+/// Smallest and largest number of players a game can be created for.
+pub const MIN_PLAYERS: u8 = 2;
+pub const MAX_PLAYERS: u8 = 8;
+
+/// How players are grouped into sides.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TeamMode {
+    /// Every player is on a side of their own.
+    FreeForAll,
+    /// Players are split into the given number of teams, which win together.
+    Teams(u8),
+}
+
+/// Settings chosen by the player creating a game.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct GameSettings {
+    pub name: String,
+    pub map: String,
+    pub player_count: u8,
+    pub teams: TeamMode,
+    /// Whether rockets damage the planets of allies
+    pub friendly_fire: bool,
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        Self {
+            name: "New Game".to_string(),
+            map: MAPS[0].to_string(),
+            player_count: MIN_PLAYERS,
+            teams: TeamMode::FreeForAll,
+            friendly_fire: false,
+        }
+    }
+}
+
+/// A player which has joined a game in the lobby.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct LobbyPlayer {
+    pub name: String,
+    pub ready: bool,
+    /// Team chosen by the player, only meaningful if the game is played in teams
+    pub team: u8,
+}
+
+/// A game which has not started yet, as seen from the lobby.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct GameInfo {
+    pub id: GameId,
+    pub settings: GameSettings,
+    pub players: Vec<LobbyPlayer>,
+}
+
+impl GameInfo {
+    pub fn is_full(&self) -> bool {
+        self.players.len() >= self.settings.player_count as usize
+    }
+
+    /// Returns whether the game is full, every player is ready and there are opposing sides.
+    pub fn can_start(&self) -> bool {
+        let first_team = self.team(0);
+        let opposed = (1..self.players.len()).any(|player| self.team(player) != first_team);
+        self.is_full() && opposed && self.players.iter().all(|player| player.ready)
+    }
+
+    /// Returns the team of the player with the given index.
+    /// In free-for-all games every player has a team of their own.
+    pub fn team(&self, player: usize) -> u8 {
+        match self.settings.teams {
+            TeamMode::FreeForAll => player as u8,
+            TeamMode::Teams(_) => self.players[player].team,
+        }
+    }
+
+    /// Returns the team with the fewest players, which new players are assigned to.
+    pub fn smallest_team(&self) -> u8 {
+        let team_count = match self.settings.teams {
+            TeamMode::FreeForAll => return 0,
+            TeamMode::Teams(count) => count,
+        };
+        (0..team_count)
+            .min_by_key(|&team| self.players.iter().filter(|p| p.team == team).count())
+            .unwrap_or(0)
+    }
+}
+
+/// Requests a client can make while it is not in a match.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum LobbyRequest {
+    ListGames,
+    CreateGame(GameSettings),
+    JoinGame(GameId),
+    SetReady(bool),
+    /// Switches to another team, if the game is played in teams.
+    SetTeam(u8),
+    LeaveGame,
+}
+
+/// Answers and updates the server sends to clients in the lobby.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum LobbyResponse {
+    GameList(Vec<GameInfo>),
+    /// Sent to all players of a game whenever it changed, including after joining it.
+    GameUpdated(GameInfo),
+    LeftGame,
+    /// The match for the given game has started, the player has the given index in it.
+    /// Everybody plays with the server's balance, whose hash is sent along so that players can
+    /// tell whether it matches their own balance file.
+    MatchStarted {
+        game: GameInfo,
+        player: u8,
+        balance: Balance,
+        balance_hash: u64,
+    },
+    Error(String),
+}
@@ -0,0 +1,147 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use super::{Delivery, NetworkBackend, NetworkSimulationEvent};
+
+type Inbox = Arc<Mutex<VecDeque<NetworkSimulationEvent>>>;
+
+/// Source of the made-up addresses identifying loopback endpoints.
+static NEXT_LOOPBACK_PORT: AtomicU16 = AtomicU16::new(1);
+
+/// Network backend passing messages between endpoints within the same process.
+///
+/// A server endpoint is created with `new`, clients are then attached to it with `connect`.
+pub struct LoopbackBackend {
+    addr: SocketAddr,
+    inbox: Inbox,
+    peers: Arc<Mutex<HashMap<SocketAddr, Inbox>>>,
+}
+
+impl LoopbackBackend {
+    pub fn new() -> Self {
+        let port = NEXT_LOOPBACK_PORT.fetch_add(1, Ordering::Relaxed);
+        Self {
+            addr: SocketAddr::from(([0, 0, 0, 0], port)),
+            inbox: Arc::default(),
+            peers: Arc::default(),
+        }
+    }
+
+    /// Creates a new endpoint which is connected to this one.
+    pub fn connect(&self) -> LoopbackBackend {
+        let client = LoopbackBackend::new();
+        client.peers.lock().unwrap().insert(self.addr, self.inbox.clone());
+        self.peers.lock().unwrap().insert(client.addr, client.inbox.clone());
+
+        client.inbox.lock().unwrap().push_back(NetworkSimulationEvent::Connect(self.addr));
+        self.inbox.lock().unwrap().push_back(NetworkSimulationEvent::Connect(client.addr));
+        client
+    }
+
+    /// Returns the made-up address identifying this endpoint.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Default for LoopbackBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkBackend for LoopbackBackend {
+    fn send(&mut self, destination: SocketAddr, payload: &[u8], _: Delivery) -> io::Result<()> {
+        let peers = self.peers.lock().unwrap();
+        let inbox = peers.get(&destination).ok_or(io::ErrorKind::NotConnected)?;
+        let message = NetworkSimulationEvent::Message(self.addr, payload.to_vec());
+        inbox.lock().unwrap().push_back(message);
+        Ok(())
+    }
+
+    fn peers(&self) -> Vec<SocketAddr> {
+        self.peers.lock().unwrap().keys().copied().collect()
+    }
+
+    fn poll(&mut self) -> Vec<NetworkSimulationEvent> {
+        let events: Vec<_> = self.inbox.lock().unwrap().drain(..).collect();
+        for event in &events {
+            if let NetworkSimulationEvent::Disconnect(addr) = event {
+                self.peers.lock().unwrap().remove(addr);
+            }
+        }
+        events
+    }
+}
+
+impl Drop for LoopbackBackend {
+    fn drop(&mut self) {
+        for inbox in self.peers.lock().unwrap().values() {
+            inbox.lock().unwrap().push_back(NetworkSimulationEvent::Disconnect(self.addr));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_make_a_round_trip() {
+        let mut server = LoopbackBackend::new();
+        let mut client = server.connect();
+        let (server_addr, client_addr) = (server.addr(), client.addr());
+        assert!(matches!(
+            server.poll().as_slice(),
+            [NetworkSimulationEvent::Connect(addr)] if *addr == client_addr
+        ));
+        assert!(matches!(
+            client.poll().as_slice(),
+            [NetworkSimulationEvent::Connect(addr)] if *addr == server_addr
+        ));
+
+        client.send(server_addr, b"ping", Delivery::Reliable).unwrap();
+        let received = server.poll();
+        assert!(matches!(
+            received.as_slice(),
+            [NetworkSimulationEvent::Message(addr, payload)]
+                if *addr == client_addr && payload == b"ping"
+        ));
+
+        server.send(client_addr, b"pong", Delivery::Unreliable).unwrap();
+        let received = client.poll();
+        assert!(matches!(
+            received.as_slice(),
+            [NetworkSimulationEvent::Message(addr, payload)]
+                if *addr == server_addr && payload == b"pong"
+        ));
+    }
+
+    #[test]
+    fn dropped_peers_disconnect() {
+        let mut server = LoopbackBackend::new();
+        let client = server.connect();
+        let client_addr = client.addr();
+        server.poll();
+        assert_eq!(server.peers(), vec![client_addr]);
+
+        drop(client);
+        assert!(matches!(
+            server.poll().as_slice(),
+            [NetworkSimulationEvent::Disconnect(addr)] if *addr == client_addr
+        ));
+        assert!(server.peers().is_empty());
+        let result = server.send(client_addr, b"lost", Delivery::Reliable);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotConnected);
+    }
+}
@@ -1,40 +1,122 @@
 // Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
 // Distributed under terms of the MIT license.
 
+mod chat;
+mod client;
+mod conditioner;
+mod delay;
+mod discovery;
+mod lobby;
+mod loopback;
+mod pending;
+mod server;
+mod sync;
+mod tcp;
 mod time;
+mod time_control;
+mod udp;
 
 use std::{
     collections::VecDeque,
-    io::Write,
-    net::{SocketAddr, TcpStream},
+    io,
+    net::SocketAddr,
+    sync::Mutex,
 };
 
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::building::*;
-use crate::components::{Aura, Moon, Rocket};
-use self::time::*;
+use crate::components::Aura;
+use crate::players::Players;
+pub use self::chat::*;
+pub use self::client::*;
+pub use self::conditioner::*;
+pub use self::delay::*;
+pub use self::discovery::*;
+pub use self::lobby::*;
+pub use self::loopback::*;
+pub use self::pending::*;
+pub use self::server::*;
+pub use self::sync::*;
+pub use self::tcp::*;
+pub use self::time::*;
+pub use self::time_control::*;
+pub use self::udp::*;
+
+/// Version of the messages exchanged between clients and servers, bumped on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 5;
 
 /// Player issued actions in the game which need to be processed through the server.
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum PlayerAction {
     Build { building: BuildingType, moon: u32 },
     ChangeAura { aura: Option<Aura>, planet: u32 },
-    ShootRocket { pos: Vec2, dir: Vec2 },
+    /// Launches a rocket in the given direction, `power` scales its speed.
+    ShootRocket { pos: Vec2, dir: Vec2, power: f32 },
+    Pause,
+    Resume,
+    /// Changes the game speed to one of `GAME_SPEEDS`.
+    SetGameSpeed { speed: f32 },
+}
+
+/// A player issued action together with the index of the issuing player.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct IssuedAction {
+    pub player: u8,
+    pub action: PlayerAction,
 }
 
 /// A single frame of the server's simulation.
 /// Contains a set of player issued actions which are executed on that frame of the simulation.
 #[derive(Deserialize, Serialize, Debug)]
 pub struct ServerTurn {
-    actions: Vec<PlayerAction>,
+    frame: u32,
+    actions: Vec<IssuedAction>,
 }
 
 impl ServerTurn {
-    pub fn new(actions: Vec<PlayerAction>) -> Self {
-        ServerTurn { actions }
+    pub fn new(frame: u32, actions: Vec<IssuedAction>) -> Self {
+        ServerTurn { frame, actions }
     }
+
+    /// Returns the simulation frame on which this turn is executed.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+}
+
+/// Messages sent from a client to the server.
+#[derive(Deserialize, Serialize, Debug)]
+pub enum ClientMessage {
+    /// An action issued by the player, numbered so the server's answer can be matched to it.
+    Action { sequence: u32, action: PlayerAction },
+    /// A chat message to relay, which does not wait for any turn.
+    Chat { channel: ChatChannel, text: String },
+    /// A marker to show to the player's team at the given position.
+    MapPing { position: Vec2 },
+    /// Tells the server that the match is decided in the client's simulation.
+    MatchOver,
+    Ping(Ping),
+    Pong(Pong),
+    Lobby(LobbyRequest),
+}
+
+/// Messages sent from the server to its clients.
+#[derive(Deserialize, Serialize, Debug)]
+pub enum ServerMessage {
+    Turn(ServerTurn),
+    /// Tells the issuing client on which frame its action will be executed.
+    ActionScheduled { sequence: u32, frame: u32 },
+    /// Tells the issuing client that its action was not allowed and will not be executed.
+    ActionRejected { sequence: u32 },
+    /// The given player resumed the paused match, which takes effect right away.
+    Resumed { player: u8 },
+    Chat(ChatMessage),
+    MapPing(MapPing),
+    Ping(Ping),
+    Pong(Pong),
+    Lobby(LobbyResponse),
 }
 
 #[derive(Debug)]
@@ -44,25 +126,171 @@ pub enum NetworkSimulationEvent {
     Disconnect(SocketAddr),
 }
 
+/// Guarantees a message is sent with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// The message is resent until it arrives, e.g. for player actions and turns.
+    Reliable,
+    /// The message may get lost, e.g. for pings which are outdated when resent anyway.
+    Unreliable,
+}
+
+/// A way of exchanging messages with other game instances, e.g. over TCP or within the process.
+pub trait NetworkBackend: Send + Sync {
+    /// Sends a single message to the peer with the given address.
+    /// Backends which are reliable anyway are free to ignore the requested delivery.
+    fn send(
+        &mut self,
+        destination: SocketAddr,
+        payload: &[u8],
+        delivery: Delivery,
+    ) -> io::Result<()>;
+
+    /// Returns the addresses of all currently connected peers.
+    fn peers(&self) -> Vec<SocketAddr>;
+
+    /// Handles connection changes and incoming data, returning the resulting events.
+    fn poll(&mut self) -> Vec<NetworkSimulationEvent>;
+}
+
+impl<B: NetworkBackend + ?Sized> NetworkBackend for Box<B> {
+    fn send(
+        &mut self,
+        destination: SocketAddr,
+        payload: &[u8],
+        delivery: Delivery,
+    ) -> io::Result<()> {
+        (**self).send(destination, payload, delivery)
+    }
+
+    fn peers(&self) -> Vec<SocketAddr> {
+        (**self).peers()
+    }
+
+    fn poll(&mut self) -> Vec<NetworkSimulationEvent> {
+        (**self).poll()
+    }
+}
+
+/// Resource holding the network backend in use, if there is a connection already.
+pub struct Network {
+    backend: Option<Box<dyn NetworkBackend>>,
+}
+
+impl Network {
+    pub fn new(backend: Box<dyn NetworkBackend>) -> Self {
+        Network {
+            backend: Some(backend),
+        }
+    }
+
+    /// Starts using the given backend, replacing the previous one.
+    pub fn connect(&mut self, backend: Box<dyn NetworkBackend>) {
+        self.backend = Some(backend);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    pub fn backend(&self) -> Option<&dyn NetworkBackend> {
+        self.backend.as_deref()
+    }
+
+    pub fn backend_mut(&mut self) -> Option<&mut (dyn NetworkBackend + 'static)> {
+        self.backend.as_deref_mut()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetworkRole {
+    Client,
+    Server,
+}
+
 /// This plugin can be added into a Bevy app to add network functionality.
-pub struct NetworkPlugin;
+/// The backend passed on creation determines how messages are actually exchanged.
+pub struct NetworkPlugin {
+    role: NetworkRole,
+    backend: Mutex<Option<Box<dyn NetworkBackend>>>,
+}
+
+impl NetworkPlugin {
+    /// Creates the plugin for a game client connected to a server through the given backend.
+    pub fn client(backend: impl NetworkBackend + 'static) -> Self {
+        Self::new(NetworkRole::Client, Box::new(backend))
+    }
+
+    /// Creates the plugin for a server reachable by its clients through the given backend.
+    pub fn server(backend: impl NetworkBackend + 'static) -> Self {
+        Self::new(NetworkRole::Server, Box::new(backend))
+    }
+
+    /// Creates the plugin for a game client which connects to a server later on,
+    /// see `Network::connect`.
+    pub fn disconnected_client() -> Self {
+        Self {
+            role: NetworkRole::Client,
+            backend: Mutex::new(None),
+        }
+    }
+
+    fn new(role: NetworkRole, backend: Box<dyn NetworkBackend>) -> Self {
+        Self {
+            role,
+            backend: Mutex::new(Some(backend)),
+        }
+    }
+}
 
 impl Plugin for NetworkPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        let stream = TcpStream::connect("127.0.0.1:7777").unwrap();
-        stream.set_nonblocking(true).unwrap();
-        app.add_resource(stream)
-            .add_resource(Events::<NetworkSimulationEvent>::default())
+        let backend = self.backend.lock().unwrap().take();
+        app.add_resource(Network { backend })
+            .add_resource(self.role)
+            .add_event::<NetworkSimulationEvent>()
             .add_resource(Transport::default())
             .add_resource(NetworkSimulationTime::default())
             .add_system(update_simulation_time)
-            .add_system(send_messages)
-            .add_system(handle_messages);
+            .add_system(receive_messages);
+
+        match self.role {
+            NetworkRole::Client => {
+                app.add_event::<LobbyResponse>()
+                    .add_event::<ChatMessage>()
+                    .add_event::<MapPing>()
+                    .add_resource(Players::default())
+                    .add_resource(TimeControl::default())
+                    .add_resource(ConnectionStats::default())
+                    .add_resource(TurnBuffer::default())
+                    .add_resource(PendingActions::default())
+                    .add_system(send_pings)
+                    .add_system(handle_messages)
+                    .add_system(execute_turns)
+                    .add_system(report_match_over)
+                    .add_system(pending_markers);
+            }
+            NetworkRole::Server => {
+                app.add_resource(ServerConnections::default())
+                    .add_resource(MatchTeams::default())
+                    .add_resource(TurnScheduler::default())
+                    .add_resource(TimeControl::default())
+                    .add_system(apply_time_controls)
+                    .add_system(send_server_pings)
+                    .add_system(handle_client_messages)
+                    .add_system(send_turns);
+            }
+        }
+
+        app.add_system(send_messages);
     }
 }
 
 pub struct Message {
     length: u16,
+    /// Receiver of the message, or `None` to send it to every connected peer.
+    pub destination: Option<SocketAddr>,
+    pub delivery: Delivery,
     pub payload: Vec<u8>,
 }
 
@@ -73,12 +301,29 @@ pub struct Transport {
 
 impl Transport {
     pub fn send(&mut self, payload: Vec<u8>) {
+        self.send_with(None, Delivery::Reliable, payload);
+    }
+
+    /// Queues a message which is only sent to the peer with the given address.
+    pub fn send_to(&mut self, destination: SocketAddr, payload: Vec<u8>) {
+        self.send_with(Some(destination), Delivery::Reliable, payload);
+    }
+
+    /// Queues a message for the given peer (or all peers if `None`) with the given guarantees.
+    pub fn send_with(
+        &mut self,
+        destination: Option<SocketAddr>,
+        delivery: Delivery,
+        payload: Vec<u8>,
+    ) {
         if payload.len() >= 65536 {
             panic!("Payload to large for u16 length field!");
         }
 
         self.messages.push_back(Message {
             length: payload.len() as u16,
+            destination,
+            delivery,
             payload,
         });
     }
@@ -88,52 +333,33 @@ impl Transport {
     }
 }
 
-fn send_messages(mut transport: ResMut<Transport>, mut stream: ResMut<TcpStream>) {
+fn send_messages(mut transport: ResMut<Transport>, mut network: ResMut<Network>) {
     let messages = transport.drain_messages();
+    let backend = match network.backend_mut() {
+        Some(backend) => backend,
+        // nobody to send to yet
+        None => return,
+    };
     for message in messages {
-        if let Err(e) = stream.write_all(&message.payload) {
-            error!("Failed to send network message: {}", e);
+        let destinations = match message.destination {
+            Some(addr) => vec![addr],
+            None => backend.peers(),
+        };
+        for addr in destinations {
+            if let Err(e) = backend.send(addr, &message.payload, message.delivery) {
+                error!("Failed to send network message to {}: {}", addr, e);
+            }
         }
     }
 }
 
-fn handle_messages(
-    commands: &mut Commands,
-    mut stream: ResMut<TcpStream>,
-    mut event_channel: ResMut<Events<NetworkSimulationEvent>>,
-    mut moon_query: Query<(Mut<Moon>, Mut<TextureAtlasSprite>)>,
-    texture_atlases: Res<Assets<TextureAtlas>>,
+fn receive_messages(
+    mut network: ResMut<Network>,
+    mut network_events: ResMut<Events<NetworkSimulationEvent>>,
 ) {
-    let peer_addr = stream.peer_addr().unwrap();
-
-    if let Ok(turn) = bincode::deserialize_from::<&mut TcpStream, ServerTurn>(&mut *stream) {
-        trace!("Received msg: {:?}", turn);
-        for action in turn.actions {
-            match action {
-                PlayerAction::Build { building, moon } => {
-                    let (mut moon, mut sprite) = moon_query.get_mut(Entity::new(moon)).unwrap();
-                    sprite.index = building_moon_texture_index(building);
-                    moon.building = Some(building);
-                },
-                PlayerAction::ShootRocket { pos, dir } => {
-                    let angle = dir.y.atan2(dir.x);
-                    commands.spawn(SpriteSheetBundle {
-                        sprite: TextureAtlasSprite::new(7),
-                        texture_atlas: texture_atlases.get_handle("SPRITE_SHEET"),
-                        transform: Transform {
-                            translation: pos.extend(0.0),
-                            rotation: Quat::from_rotation_z(angle),
-                            scale: Vec3::splat(0.25),
-                        },
-                        ..Default::default()
-                    })
-                    .with(Rocket {
-                        velocity: 300.0 * dir,
-                    });
-                }
-                _ => {}
-            }
+    if let Some(backend) = network.backend_mut() {
+        for event in backend.poll() {
+            network_events.send(event);
         }
-        //event_channel.send(NetworkSimulationEvent::Message(peer_addr, msg_payload));
     }
 }
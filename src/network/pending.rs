@@ -0,0 +1,163 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::prelude::*;
+
+use crate::balance::Balance;
+use crate::components::{BodyId, Moon};
+use super::{ClientMessage, PlayerAction, Transport, INITIAL_INPUT_DELAY};
+
+/// An action issued by the local player which has not been executed yet.
+#[derive(Debug)]
+pub struct PendingAction {
+    pub sequence: u32,
+    pub action: PlayerAction,
+    /// Frame the action was issued on
+    pub issued_frame: u32,
+    /// Frame the server scheduled the action for, `None` until the server answered
+    pub frame: Option<u32>,
+}
+
+/// Actions issued by the local player which are still waiting for their execution frame.
+#[derive(Default)]
+pub struct PendingActions {
+    next_sequence: u32,
+    actions: Vec<PendingAction>,
+    /// Frames between issuing the last scheduled action and its execution
+    last_delay: Option<u32>,
+}
+
+impl PendingActions {
+    /// Sends the action issued on the given frame to the server and keeps track of it until it is
+    /// executed.
+    pub fn submit(&mut self, action: PlayerAction, frame: u32, transport: &mut Transport) {
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        let message = ClientMessage::Action {
+            sequence,
+            action: action.clone(),
+        };
+        let serialized = bincode::serialize(&message).unwrap();
+        transport.send(serialized);
+
+        self.actions.push(PendingAction {
+            sequence,
+            action,
+            issued_frame: frame,
+            frame: None,
+        });
+    }
+
+    /// Records the frame the server scheduled the action with the given sequence number for.
+    pub fn scheduled(&mut self, sequence: u32, frame: u32) {
+        if let Some(pending) = self.actions.iter_mut().find(|p| p.sequence == sequence) {
+            pending.frame = Some(frame);
+            self.last_delay = Some(frame.saturating_sub(pending.issued_frame));
+        }
+    }
+
+    /// Returns the frame an action issued on the given frame is probably executed on, judging by
+    /// how long the last one took.
+    pub fn expected_frame(&self, issued_frame: u32) -> u32 {
+        issued_frame + self.last_delay.unwrap_or(INITIAL_INPUT_DELAY)
+    }
+
+    /// Forgets the action with the given sequence number, which the server refused to execute.
+    pub fn rejected(&mut self, sequence: u32) {
+        self.actions.retain(|p| p.sequence != sequence);
+    }
+
+    /// Forgets all actions which were scheduled for frames up to and including the given one.
+    pub fn executed_until(&mut self, frame: u32) {
+        self.actions.retain(|p| p.frame.map_or(true, |f| f > frame));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PendingAction> {
+        self.actions.iter()
+    }
+
+    pub fn contains(&self, sequence: u32) -> bool {
+        self.actions.iter().any(|p| p.sequence == sequence)
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+/// Marks the target of a pending action in the world.
+pub struct PendingMarker {
+    sequence: u32,
+}
+
+/// System showing translucent previews of pending actions until they are executed.
+pub fn pending_markers(
+    commands: &mut Commands,
+    pending: Res<PendingActions>,
+    balance: Res<Balance>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    marker_query: Query<(Entity, &PendingMarker)>,
+    moon_query: Query<(Entity, &BodyId, &Moon)>,
+) {
+    let mut marked = Vec::new();
+    for (entity, marker) in marker_query.iter() {
+        if pending.contains(marker.sequence) {
+            marked.push(marker.sequence);
+        } else {
+            commands.despawn(entity);
+        }
+    }
+
+    for pending in pending.iter().filter(|p| !marked.contains(&p.sequence)) {
+        let marker = PendingMarker {
+            sequence: pending.sequence,
+        };
+        match pending.action {
+            PlayerAction::Build { building, moon } => {
+                let moon_entity = match moon_query.iter().find(|(_, id, _)| id.0 == moon) {
+                    Some((entity, _, _)) => entity,
+                    None => continue,
+                };
+                let marker_entity = commands
+                    .spawn(SpriteSheetBundle {
+                        sprite: TextureAtlasSprite {
+                            color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                            index: balance.building_moon_texture_index(building),
+                        },
+                        texture_atlas: texture_atlases.get_handle("SPRITE_SHEET"),
+                        transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.1)),
+                        ..Default::default()
+                    })
+                    .with(marker)
+                    .current_entity()
+                    .unwrap();
+                commands.push_children(moon_entity, &[marker_entity]);
+            }
+            PlayerAction::ShootRocket { pos, dir, .. } => {
+                commands
+                    .spawn(SpriteSheetBundle {
+                        sprite: TextureAtlasSprite {
+                            color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                            index: balance.sprites.rocket,
+                        },
+                        texture_atlas: texture_atlases.get_handle("SPRITE_SHEET"),
+                        transform: Transform {
+                            translation: pos.extend(0.0),
+                            rotation: Quat::from_rotation_z(dir.y.atan2(dir.x)),
+                            scale: Vec3::splat(0.25),
+                        },
+                        ..Default::default()
+                    })
+                    .with(marker);
+            }
+            // pending aura changes are shown in the info panel instead
+            PlayerAction::ChangeAura { .. } => {}
+            PlayerAction::Pause | PlayerAction::Resume | PlayerAction::SetGameSpeed { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+};
+
+use bevy::prelude::*;
+
+use super::*;
+
+/// Statistics of all clients currently connected to the server.
+#[derive(Default)]
+pub struct ServerConnections {
+    pub stats: HashMap<SocketAddr, ConnectionStats>,
+    /// Addresses of all players which ever connected, indexed by player number
+    players: Vec<SocketAddr>,
+    /// Addresses of the players whose simulation has decided the match
+    match_over: HashSet<SocketAddr>,
+}
+
+impl ServerConnections {
+    /// Returns the player number of the client with the given address.
+    /// Clients are numbered in the order they connected in, which the lobby keeps in line with
+    /// the order of players in the game.
+    pub fn player(&self, addr: SocketAddr) -> Option<u8> {
+        self.players.iter().position(|&a| a == addr).map(|index| index as u8)
+    }
+
+    /// Returns whether every connected player reported that the match is decided.
+    pub fn is_match_over(&self) -> bool {
+        !self.stats.is_empty() && self.stats.keys().all(|addr| self.match_over.contains(addr))
+    }
+
+    /// Returns the addresses of all connected players on the given player's team.
+    fn team_addrs(&self, player: u8, teams: &MatchTeams) -> Vec<SocketAddr> {
+        let team = teams.team(player);
+        (0..self.players.len())
+            .filter(|&other| teams.team(other as u8) == team)
+            .map(|other| self.players[other])
+            .filter(|addr| self.stats.contains_key(addr))
+            .collect()
+    }
+}
+
+/// Team of every player in the match, which decides who receives team chat and map pings.
+#[derive(Default)]
+pub struct MatchTeams {
+    teams: Vec<u8>,
+}
+
+impl MatchTeams {
+    pub fn new(teams: Vec<u8>) -> Self {
+        Self { teams }
+    }
+
+    /// Returns the team of the given player, players without a known team play on their own.
+    pub fn team(&self, player: u8) -> u8 {
+        self.teams.get(player as usize).copied().unwrap_or(player)
+    }
+}
+
+/// System periodically sending pings to all clients.
+pub fn send_server_pings(
+    mut state: Local<PingState>,
+    time: Res<Time>,
+    mut transport: ResMut<Transport>,
+) {
+    state.send(&time, &mut transport, None, ServerMessage::Ping);
+}
+
+#[derive(Default)]
+pub struct ServerMessageState {
+    network_event_reader: EventReader<NetworkSimulationEvent>,
+}
+
+pub fn handle_client_messages(
+    mut state: Local<ServerMessageState>,
+    time: Res<Time>,
+    mut sim_time: ResMut<NetworkSimulationTime>,
+    network_events: Res<Events<NetworkSimulationEvent>>,
+    mut connections: ResMut<ServerConnections>,
+    mut transport: ResMut<Transport>,
+    mut scheduler: ResMut<TurnScheduler>,
+    mut time_control: ResMut<TimeControl>,
+    teams: Res<MatchTeams>,
+) {
+    for event in state.network_event_reader.iter(&network_events) {
+        let (addr, payload) = match event {
+            NetworkSimulationEvent::Message(addr, payload) => (*addr, payload),
+            NetworkSimulationEvent::Connect(addr) => {
+                info!("Client {} connected", addr);
+                connections.stats.insert(*addr, ConnectionStats::default());
+                if connections.player(*addr).is_none() {
+                    connections.players.push(*addr);
+                }
+                continue;
+            }
+            NetworkSimulationEvent::Disconnect(addr) => {
+                info!("Client {} disconnected", addr);
+                connections.stats.remove(addr);
+                continue;
+            }
+        };
+
+        let message = match bincode::deserialize::<ClientMessage>(payload) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Received malformed message from {}: {}", addr, e);
+                continue;
+            }
+        };
+        trace!("Received from client: {:?}", message);
+
+        match message {
+            ClientMessage::Action { sequence, action } => {
+                let player = match connections.player(addr) {
+                    Some(player) => player,
+                    None => continue,
+                };
+                let action = IssuedAction { player, action };
+                if !time_control.allows(&action) {
+                    debug!("Rejecting action from player {}: {:?}", player, action.action);
+                    let msg = ServerMessage::ActionRejected { sequence };
+                    transport.send_to(addr, bincode::serialize(&msg).unwrap());
+                    continue;
+                }
+
+                let current_frame = sim_time.frame_number();
+                let frame = if let PlayerAction::Resume = action.action {
+                    time_control.apply(&action, current_frame, &mut sim_time);
+                    let msg = ServerMessage::Resumed { player };
+                    transport.send(bincode::serialize(&msg).unwrap());
+                    current_frame
+                } else {
+                    let frame = scheduler.schedule(action.clone(), current_frame);
+                    if TimeControl::is_time_action(&action.action) {
+                        time_control.schedule(frame, action);
+                    }
+                    frame
+                };
+                let msg = ServerMessage::ActionScheduled { sequence, frame };
+                let serialized = bincode::serialize(&msg).unwrap();
+                transport.send_to(addr, serialized);
+            }
+            ClientMessage::Chat { channel, mut text } => {
+                let player = match connections.player(addr) {
+                    Some(player) => player,
+                    None => continue,
+                };
+                text = text.trim().chars().take(MAX_CHAT_LENGTH).collect();
+                if text.is_empty() {
+                    continue;
+                }
+                let message = ServerMessage::Chat(ChatMessage {
+                    player,
+                    channel,
+                    text,
+                });
+                let serialized = bincode::serialize(&message).unwrap();
+                match channel {
+                    ChatChannel::All => transport.send(serialized),
+                    ChatChannel::Team => {
+                        for addr in connections.team_addrs(player, &teams) {
+                            transport.send_to(addr, serialized.clone());
+                        }
+                    }
+                }
+            }
+            ClientMessage::MapPing { position } => {
+                let player = match connections.player(addr) {
+                    Some(player) => player,
+                    None => continue,
+                };
+                let message = ServerMessage::MapPing(MapPing { player, position });
+                let serialized = bincode::serialize(&message).unwrap();
+                for addr in connections.team_addrs(player, &teams) {
+                    transport.send_to(addr, serialized.clone());
+                }
+            }
+            ClientMessage::MatchOver => {
+                info!("Client {} reports the match to be decided", addr);
+                connections.match_over.insert(addr);
+            }
+            ClientMessage::Ping(ping) => {
+                let pong = ping.answer(time.seconds_since_startup, &sim_time);
+                let serialized = bincode::serialize(&ServerMessage::Pong(pong)).unwrap();
+                transport.send_with(Some(addr), Delivery::Unreliable, serialized);
+            }
+            ClientMessage::Pong(pong) => {
+                let stats = connections.stats.entry(addr).or_default();
+                stats.update(&pong, time.seconds_since_startup);
+                debug!(
+                    "RTT to {}: {:.1}ms (jitter {:.1}ms)",
+                    addr,
+                    stats.rtt * 1000.0,
+                    stats.jitter * 1000.0
+                );
+            }
+            ClientMessage::Lobby(request) => {
+                warn!("Ignoring lobby request from {} during match: {:?}", addr, request);
+            }
+        }
+    }
+}
+
+/// System sending out the turns for all frames which can no longer receive actions.
+pub fn send_turns(
+    time: Res<Time>,
+    sim_time: Res<NetworkSimulationTime>,
+    connections: Res<ServerConnections>,
+    mut scheduler: ResMut<TurnScheduler>,
+    mut transport: ResMut<Transport>,
+) {
+    let stats = connections.stats.values();
+    scheduler.adjust_delay(time.delta_seconds, sim_time.per_frame_duration(), stats);
+
+    for turn in scheduler.close_turns(sim_time.frame_number()) {
+        let serialized = bincode::serialize(&ServerMessage::Turn(turn)).unwrap();
+        transport.send(serialized);
+    }
+}
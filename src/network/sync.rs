@@ -0,0 +1,238 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::net::SocketAddr;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{Delivery, NetworkSimulationTime, Transport};
+
+/// Seconds between two pings sent over the same connection.
+pub const PING_INTERVAL: f32 = 1.0;
+
+/// Weight of a new sample in the exponentially weighted moving averages.
+const SMOOTHING: f32 = 0.125;
+
+/// If the local frame counter is off by more frames than this, it is reset instead of nudged.
+const MAX_FRAME_DRIFT: f32 = 30.0;
+
+/// Fraction of the frame drift which is corrected on every pong.
+const DRIFT_CORRECTION: f32 = 0.1;
+
+/// Request for a `Pong`, used to measure round-trip time and frame drift.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct Ping {
+    pub sequence: u32,
+    /// Sender's clock when the ping was sent (in seconds)
+    pub sent_at: f64,
+}
+
+impl Ping {
+    /// Creates the `Pong` answering this ping.
+    pub fn answer(&self, now: f64, sim_time: &NetworkSimulationTime) -> Pong {
+        Pong {
+            sequence: self.sequence,
+            ping_sent_at: self.sent_at,
+            // the ping is answered right away, so receive and send time are the same
+            received_at: now,
+            sent_at: now,
+            frame: sim_time.frame_number(),
+            frame_elapsed: sim_time.elapsed_duration(),
+        }
+    }
+}
+
+/// Answer to a `Ping`, carrying the responder's clock and simulation frame.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug)]
+pub struct Pong {
+    pub sequence: u32,
+    /// Pinging side's clock when the ping was sent (in seconds)
+    pub ping_sent_at: f64,
+    /// Responder's clock when the ping was received (in seconds)
+    pub received_at: f64,
+    /// Responder's clock when the pong was sent (in seconds)
+    pub sent_at: f64,
+    /// Responder's simulation frame when the pong was sent
+    pub frame: u32,
+    /// Time the responder has accumulated towards its next simulation frame (in seconds)
+    pub frame_elapsed: f32,
+}
+
+/// Round-trip time and jitter estimates for a single connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// Smoothed round-trip time (in seconds)
+    pub rtt: f32,
+    /// Smoothed mean deviation of the round-trip time (in seconds)
+    pub jitter: f32,
+    /// Number of frames the local simulation was behind the remote one at the last pong
+    pub frame_drift: f32,
+    /// Number of pongs received so far
+    pub samples: u32,
+}
+
+impl ConnectionStats {
+    /// Updates the estimates with the timestamps of a pong received at local time `now`.
+    pub fn update(&mut self, pong: &Pong, now: f64) {
+        // NTP-style estimation, see RFC 5905
+        let rtt = ((now - pong.ping_sent_at) - (pong.sent_at - pong.received_at)).max(0.0) as f32;
+
+        if self.samples == 0 {
+            self.rtt = rtt;
+            self.jitter = rtt / 2.0;
+        } else {
+            self.jitter += SMOOTHING * ((self.rtt - rtt).abs() - self.jitter);
+            self.rtt += SMOOTHING * (rtt - self.rtt);
+        }
+        self.samples += 1;
+    }
+}
+
+/// Moves the local simulation frame counter towards the remote one reported in the pong.
+/// Small drifts are corrected smoothly, large ones by jumping to the estimated frame.
+pub fn synchronize_frames(
+    sim_time: &mut NetworkSimulationTime,
+    stats: &mut ConnectionStats,
+    pong: &Pong,
+) {
+    // the remote simulation stands still as well, no need to catch up on it
+    if sim_time.is_paused() {
+        return;
+    }
+    let per_frame = sim_time.per_frame_duration();
+    let travel_time = stats.rtt / 2.0 * sim_time.speed();
+    let remote_frame = pong.frame as f32 + (pong.frame_elapsed + travel_time) / per_frame;
+    let local_frame = sim_time.frame_number() as f32 + sim_time.elapsed_duration() / per_frame;
+    let drift = remote_frame - local_frame;
+    stats.frame_drift = drift;
+
+    if drift.abs() > MAX_FRAME_DRIFT {
+        debug!("Resynchronizing simulation frame, drift was {} frames", drift);
+        sim_time.set_frame_number(remote_frame as u32);
+    } else {
+        sim_time.update_elapsed(DRIFT_CORRECTION * drift * per_frame);
+    }
+}
+
+pub struct PingState {
+    timer: Timer,
+    next_sequence: u32,
+}
+
+impl Default for PingState {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(PING_INTERVAL, true),
+            next_sequence: 0,
+        }
+    }
+}
+
+impl PingState {
+    /// Advances the ping timer and sends a new ping to the given peer (or all peers if `None`)
+    /// if it is time for one, wrapped into the sending side's message type by `message`.
+    pub fn send<M: Serialize>(
+        &mut self,
+        time: &Time,
+        transport: &mut Transport,
+        destination: Option<SocketAddr>,
+        message: impl FnOnce(Ping) -> M,
+    ) {
+        if !self.timer.tick(time.delta_seconds).just_finished() {
+            return;
+        }
+
+        let ping = Ping {
+            sequence: self.next_sequence,
+            sent_at: time.seconds_since_startup,
+        };
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        let serialized = bincode::serialize(&message(ping)).unwrap();
+        // pings are outdated by the time they would be resent
+        transport.send_with(destination, Delivery::Unreliable, serialized);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+    }
+
+    /// Pong to a ping sent at local time 10s, which the remote side took 10ms to answer.
+    fn pong(frame: u32, frame_elapsed: f32) -> Pong {
+        Pong {
+            sequence: 0,
+            ping_sent_at: 10.0,
+            received_at: 100.05,
+            sent_at: 100.06,
+            frame,
+            frame_elapsed,
+        }
+    }
+
+    #[test]
+    fn first_sample_sets_the_estimates() {
+        let mut stats = ConnectionStats::default();
+        stats.update(&pong(0, 0.0), 10.11);
+        // the time the remote side took to answer does not count towards the round trip
+        assert_close(stats.rtt, 0.1);
+        assert_close(stats.jitter, 0.05);
+        assert_eq!(stats.samples, 1);
+    }
+
+    #[test]
+    fn later_samples_are_smoothed() {
+        let mut stats = ConnectionStats::default();
+        stats.update(&pong(0, 0.0), 10.11);
+        stats.update(&pong(0, 0.0), 10.19);
+        assert_close(stats.rtt, 0.1 + SMOOTHING * 0.08);
+        assert_close(stats.jitter, 0.05 + SMOOTHING * (0.08 - 0.05));
+        assert_eq!(stats.samples, 2);
+    }
+
+    #[test]
+    fn round_trip_time_is_never_negative() {
+        let mut stats = ConnectionStats::default();
+        stats.update(&pong(0, 0.0), 10.005);
+        assert_close(stats.rtt, 0.0);
+    }
+
+    #[test]
+    fn small_drifts_are_nudged() {
+        let mut sim_time = NetworkSimulationTime::default();
+        let mut stats = ConnectionStats::default();
+        synchronize_frames(&mut sim_time, &mut stats, &pong(10, 0.0));
+        assert_close(stats.frame_drift, 10.0);
+        assert_eq!(sim_time.frame_number(), 0);
+        let per_frame = sim_time.per_frame_duration();
+        assert_close(sim_time.elapsed_duration(), DRIFT_CORRECTION * 10.0 * per_frame);
+    }
+
+    #[test]
+    fn large_drifts_jump_to_the_remote_frame() {
+        let mut sim_time = NetworkSimulationTime::default();
+        let mut stats = ConnectionStats {
+            rtt: 0.2,
+            ..Default::default()
+        };
+        // the pong travelled for half the round trip, i.e. three frames
+        let per_frame = sim_time.per_frame_duration();
+        synchronize_frames(&mut sim_time, &mut stats, &pong(100, per_frame / 2.0));
+        assert_close(stats.frame_drift, 103.5);
+        assert_eq!(sim_time.frame_number(), 103);
+    }
+
+    #[test]
+    fn paused_simulations_are_not_synchronized() {
+        let mut sim_time = NetworkSimulationTime::default();
+        sim_time.set_paused(true);
+        let mut stats = ConnectionStats::default();
+        synchronize_frames(&mut sim_time, &mut stats, &pong(100, 0.0));
+        assert_eq!(sim_time.frame_number(), 0);
+        assert_close(stats.frame_drift, 0.0);
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+};
+
+use bevy::prelude::*;
+use bytes::{Buf, BytesMut};
+
+use super::{Delivery, NetworkBackend, NetworkSimulationEvent};
+
+/// Network backend sending length-prefixed messages over a TCP stream to the server.
+pub struct TcpBackend {
+    connections: Vec<TcpConnection>,
+    /// Events which occured outside of `poll` and are reported by its next call
+    queued_events: Vec<NetworkSimulationEvent>,
+}
+
+struct TcpConnection {
+    stream: TcpStream,
+    addr: SocketAddr,
+    receive_buffer: ReceiveBuffer,
+    send_buffer: SendBuffer,
+}
+
+impl TcpBackend {
+    /// Creates a backend connected to the server at the given address.
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let mut backend = Self {
+            connections: Vec::new(),
+            queued_events: Vec::new(),
+        };
+        backend.add_connection(stream)?;
+        Ok(backend)
+    }
+
+    fn add_connection(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        let addr = stream.peer_addr()?;
+        self.connections.push(TcpConnection {
+            stream,
+            addr,
+            receive_buffer: ReceiveBuffer::default(),
+            send_buffer: SendBuffer::default(),
+        });
+        self.queued_events.push(NetworkSimulationEvent::Connect(addr));
+        Ok(())
+    }
+}
+
+impl NetworkBackend for TcpBackend {
+    fn send(&mut self, destination: SocketAddr, payload: &[u8], _: Delivery) -> io::Result<()> {
+        let conn = self
+            .connections
+            .iter_mut()
+            .find(|conn| conn.addr == destination)
+            .ok_or(io::ErrorKind::NotConnected)?;
+        // a write may only be partially done, so the rest is kept until the stream accepts it
+        conn.send_buffer.push(payload);
+        conn.send_buffer.flush(&mut conn.stream)
+    }
+
+    fn peers(&self) -> Vec<SocketAddr> {
+        self.connections.iter().map(|conn| conn.addr).collect()
+    }
+
+    fn poll(&mut self) -> Vec<NetworkSimulationEvent> {
+        let mut events: Vec<_> = self.queued_events.drain(..).collect();
+        let mut disconnected = Vec::new();
+        for conn in self.connections.iter_mut() {
+            if let Err(e) = conn.send_buffer.flush(&mut conn.stream) {
+                warn!("Lost connection to {}: {}", conn.addr, e);
+                disconnected.push(conn.addr);
+                continue;
+            }
+            match conn.receive_buffer.receive(&mut conn.stream) {
+                Ok(payloads) => {
+                    for payload in payloads {
+                        events.push(NetworkSimulationEvent::Message(conn.addr, payload));
+                    }
+                }
+                Err(e) => {
+                    warn!("Lost connection to {}: {}", conn.addr, e);
+                    disconnected.push(conn.addr);
+                }
+            }
+        }
+
+        self.connections.retain(|conn| !disconnected.contains(&conn.addr));
+        events.extend(disconnected.into_iter().map(NetworkSimulationEvent::Disconnect));
+        events
+    }
+}
+
+/// Buffer for bytes which were read from a stream but do not yet form a complete message.
+#[derive(Default)]
+pub struct ReceiveBuffer {
+    bytes: BytesMut,
+}
+
+impl ReceiveBuffer {
+    /// Reads all currently available bytes from the non-blocking stream and returns the payloads
+    /// of all messages which were completed by them.
+    pub fn receive(&mut self, stream: &mut impl Read) -> io::Result<Vec<Vec<u8>>> {
+        let mut chunk = [0; 4096];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.bytes.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut payloads = Vec::new();
+        while self.bytes.len() >= 2 {
+            let length = u16::from_be_bytes([self.bytes[0], self.bytes[1]]) as usize;
+            if self.bytes.len() < 2 + length {
+                break;
+            }
+            self.bytes.advance(2);
+            payloads.push(self.bytes.split_to(length).to_vec());
+        }
+        Ok(payloads)
+    }
+}
+
+/// Buffer for length-prefixed messages which were not yet completely written to a stream.
+#[derive(Default)]
+pub struct SendBuffer {
+    bytes: BytesMut,
+}
+
+impl SendBuffer {
+    /// Appends a message, which is written by the following calls to `flush`.
+    pub fn push(&mut self, payload: &[u8]) {
+        self.bytes.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        self.bytes.extend_from_slice(payload);
+    }
+
+    /// Writes as many of the buffered bytes as the non-blocking stream currently accepts.
+    pub fn flush(&mut self, stream: &mut impl Write) -> io::Result<()> {
+        while !self.bytes.is_empty() {
+            match stream.write(&self.bytes) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => self.bytes.advance(n),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
@@ -15,6 +15,10 @@ pub struct NetworkSimulationTime {
     per_frame_duration: f32,
     /// Number of frames the game lags behind the server simulation
     frame_lag: u32,
+    /// Simulated seconds per real second
+    speed: f32,
+    /// Whether the simulation currently stands still
+    paused: bool,
 }
 
 impl NetworkSimulationTime {
@@ -66,6 +70,34 @@ impl NetworkSimulationTime {
     pub fn frame_lag(&self) -> u32 {
         self.frame_lag
     }
+
+    /// Returns the game speed as a multiple of the normal speed
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Changes the rate at which simulation frames are run by the given factor
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Stops or restarts running simulation frames, keeping the game speed
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Returns the simulated seconds per real second, which is zero while paused
+    pub fn time_scale(&self) -> f32 {
+        if self.paused {
+            0.0
+        } else {
+            self.speed
+        }
+    }
 }
 
 impl Default for NetworkSimulationTime {
@@ -76,12 +108,15 @@ impl Default for NetworkSimulationTime {
             // 30 frames / second
             per_frame_duration: 1.0 / 30.0,
             frame_lag: 1,
+            speed: 1.0,
+            paused: false,
         }
     }
 }
 
 pub fn update_simulation_time(mut sim_time: ResMut<NetworkSimulationTime>, time: Res<Time>) {
-    sim_time.update_elapsed(time.delta_seconds);
+    let scale = sim_time.time_scale();
+    sim_time.update_elapsed(time.delta_seconds * scale);
     sim_time.reset_frame_lag();
     while sim_time.elapsed_duration() > sim_time.per_frame_duration() {
         sim_time.increment_frame_number();
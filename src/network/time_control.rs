@@ -0,0 +1,118 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::collections::{BTreeMap, HashMap};
+
+use bevy::prelude::*;
+
+use super::{IssuedAction, NetworkSimulationTime, PlayerAction};
+
+/// Number of times each player can pause a match.
+pub const MAX_PAUSES: u32 = 3;
+
+/// Game speeds players can choose from, as multiples of the normal speed.
+pub const GAME_SPEEDS: &[f32] = &[0.5, 1.0, 1.5, 2.0];
+
+/// Keeps track of pauses and game speed changes, on the server as well as on the clients.
+///
+/// Pauses and speed changes take effect on the frame they were scheduled for, like every other
+/// action. Resuming cannot wait for a frame while the simulation stands still, so the server
+/// applies it right away and tells the clients about it.
+#[derive(Default)]
+pub struct TimeControl {
+    pauses_used: HashMap<u8, u32>,
+    /// Player who paused the match, if it is paused
+    paused_by: Option<u8>,
+    /// Pauses and speed changes which were scheduled but have not taken effect yet
+    scheduled: BTreeMap<u32, Vec<IssuedAction>>,
+}
+
+impl TimeControl {
+    pub fn pauses_left(&self, player: u8) -> u32 {
+        MAX_PAUSES.saturating_sub(self.pauses_used.get(&player).copied().unwrap_or(0))
+    }
+
+    pub fn paused_by(&self) -> Option<u8> {
+        self.paused_by
+    }
+
+    /// Returns whether the action is a pause, resume or speed change.
+    pub fn is_time_action(action: &PlayerAction) -> bool {
+        matches!(
+            action,
+            PlayerAction::Pause | PlayerAction::Resume | PlayerAction::SetGameSpeed { .. }
+        )
+    }
+
+    /// Returns whether the given action is allowed in the current state of the match.
+    /// Actions which do not affect the game's time are always allowed.
+    pub fn allows(&self, issued: &IssuedAction) -> bool {
+        let pause_scheduled = self
+            .scheduled
+            .values()
+            .flatten()
+            .any(|scheduled| matches!(scheduled.action, PlayerAction::Pause));
+        match issued.action {
+            PlayerAction::Pause => {
+                self.paused_by.is_none() && !pause_scheduled && self.pauses_left(issued.player) > 0
+            }
+            PlayerAction::Resume => self.paused_by.is_some(),
+            PlayerAction::SetGameSpeed { speed } => GAME_SPEEDS.contains(&speed),
+            _ => true,
+        }
+    }
+
+    /// Remembers a pause or speed change to apply once the simulation reaches the given frame.
+    pub fn schedule(&mut self, frame: u32, issued: IssuedAction) {
+        self.scheduled.entry(frame).or_default().push(issued);
+    }
+
+    /// Applies all scheduled actions for frames up to the current one.
+    pub fn apply_scheduled(&mut self, sim_time: &mut NetworkSimulationTime) {
+        while let Some(&frame) = self.scheduled.keys().next() {
+            if frame > sim_time.frame_number() {
+                break;
+            }
+            for issued in self.scheduled.remove(&frame).unwrap() {
+                self.apply(&issued, frame, sim_time);
+            }
+        }
+    }
+
+    /// Applies a pause, resume or speed change which takes effect on the given frame.
+    pub fn apply(
+        &mut self,
+        issued: &IssuedAction,
+        frame: u32,
+        sim_time: &mut NetworkSimulationTime,
+    ) {
+        match issued.action {
+            PlayerAction::Pause => {
+                info!("Player {} paused the match on frame {}", issued.player, frame);
+                *self.pauses_used.entry(issued.player).or_default() += 1;
+                self.paused_by = Some(issued.player);
+                sim_time.set_paused(true);
+                // the simulation may have run a little past the pause, it stands still on its frame
+                sim_time.set_frame_number(frame);
+            }
+            PlayerAction::Resume => {
+                info!("Player {} resumed the match", issued.player);
+                self.paused_by = None;
+                sim_time.set_paused(false);
+            }
+            PlayerAction::SetGameSpeed { speed } => {
+                info!("Player {} set the game speed to {}", issued.player, speed);
+                sim_time.set_speed(speed);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// System applying pauses and speed changes on the server once their frame is reached.
+pub fn apply_time_controls(
+    mut time_control: ResMut<TimeControl>,
+    mut sim_time: ResMut<NetworkSimulationTime>,
+) {
+    time_control.apply_scheduled(&mut sim_time);
+}
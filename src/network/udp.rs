@@ -0,0 +1,612 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::conditioner::Conditioner;
+use super::{Delivery, NetworkBackend, NetworkConditions, NetworkSimulationEvent};
+
+/// Version of the UDP protocol, peers with a different version are rejected.
+pub const UDP_PROTOCOL_VERSION: u32 = 1;
+
+/// Size datagrams are kept below, which leaves room for IP and UDP headers (and tunnels, e.g.
+/// VPNs) within the 1500 bytes of an Ethernet frame, so that they are never fragmented.
+const MAX_PACKET_SIZE: usize = 1200;
+
+/// Maximum number of message bytes per datagram.
+const MAX_FRAGMENT_SIZE: usize = 900;
+
+/// Maximum number of acknowledgements per datagram.
+/// At 4 bytes each they take up 256 bytes, so together with a full fragment and about 40 bytes
+/// of packet header a datagram stays within `MAX_PACKET_SIZE`.
+const MAX_ACKS_PER_PACKET: usize = 64;
+
+/// Size of the buffer datagrams are received into.
+const MAX_DATAGRAM_SIZE: usize = 2048;
+
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(250);
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+const INITIAL_RESEND_TIMEOUT: Duration = Duration::from_millis(200);
+const MIN_RESEND_TIMEOUT: Duration = Duration::from_millis(30);
+
+#[derive(Deserialize, Serialize, Debug)]
+enum Packet {
+    ConnectRequest { protocol_version: u32 },
+    ConnectAccepted,
+    Disconnect,
+    /// Acknowledges reliable fragments and optionally carries another fragment.
+    Data {
+        acks: Vec<u32>,
+        fragment: Option<Fragment>,
+    },
+}
+
+/// A part of a message small enough to fit into a single datagram.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct Fragment {
+    /// Sequence number of reliable fragments, which the receiver has to acknowledge
+    sequence: Option<u32>,
+    message: u32,
+    index: u16,
+    count: u16,
+    data: Vec<u8>,
+}
+
+struct SentFragment {
+    fragment: Fragment,
+    first_sent: Instant,
+    last_sent: Instant,
+    resent: bool,
+}
+
+struct Reassembly {
+    fragments: Vec<Option<Vec<u8>>>,
+    missing: usize,
+    started: Instant,
+    /// Reliable messages are never given up on, as their acknowledged fragments are not resent
+    reliable: bool,
+}
+
+/// Sequence numbers of received reliable fragments, used to drop duplicates.
+#[derive(Default)]
+struct ReceivedSequences {
+    /// All sequence numbers below this one have been received
+    complete_below: u32,
+    /// Received sequence numbers at or above `complete_below`
+    above: BTreeSet<u32>,
+}
+
+impl ReceivedSequences {
+    /// Records the sequence number, returning `false` if it was received before.
+    fn insert(&mut self, sequence: u32) -> bool {
+        if sequence < self.complete_below || !self.above.insert(sequence) {
+            return false;
+        }
+        while self.above.remove(&self.complete_below) {
+            self.complete_below += 1;
+        }
+        true
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum ConnectionState {
+    Connecting,
+    Connected,
+}
+
+struct UdpConnection {
+    addr: SocketAddr,
+    state: ConnectionState,
+    last_received: Instant,
+    last_sent: Instant,
+    next_sequence: u32,
+    next_message: u32,
+    unacked: BTreeMap<u32, SentFragment>,
+    pending_acks: Vec<u32>,
+    received: ReceivedSequences,
+    reassemblies: HashMap<u32, Reassembly>,
+    /// Smoothed round-trip time of reliable fragments
+    srtt: Option<Duration>,
+}
+
+impl UdpConnection {
+    fn new(addr: SocketAddr, state: ConnectionState, now: Instant) -> Self {
+        Self {
+            addr,
+            state,
+            last_received: now,
+            last_sent: now,
+            next_sequence: 0,
+            next_message: 0,
+            unacked: BTreeMap::new(),
+            pending_acks: Vec::new(),
+            received: ReceivedSequences::default(),
+            reassemblies: HashMap::new(),
+            srtt: None,
+        }
+    }
+
+    fn resend_timeout(&self) -> Duration {
+        match self.srtt {
+            Some(srtt) => (srtt * 3 / 2).max(MIN_RESEND_TIMEOUT),
+            None => INITIAL_RESEND_TIMEOUT,
+        }
+    }
+
+    /// Splits the message into fragments, assigning sequence numbers if it has to be reliable.
+    fn fragment(&mut self, payload: &[u8], delivery: Delivery) -> Vec<Fragment> {
+        let message = self.next_message;
+        self.next_message = self.next_message.wrapping_add(1);
+
+        let chunks: Vec<&[u8]> = if payload.is_empty() {
+            vec![payload]
+        } else {
+            payload.chunks(MAX_FRAGMENT_SIZE).collect()
+        };
+        let count = chunks.len() as u16;
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let sequence = match delivery {
+                    Delivery::Reliable => {
+                        self.next_sequence += 1;
+                        Some(self.next_sequence - 1)
+                    }
+                    Delivery::Unreliable => None,
+                };
+                Fragment {
+                    sequence,
+                    message,
+                    index: index as u16,
+                    count,
+                    data: chunk.to_vec(),
+                }
+            })
+            .collect()
+    }
+
+    fn acknowledge(&mut self, sequence: u32, now: Instant) {
+        if let Some(sent) = self.unacked.remove(&sequence) {
+            // Karn's algorithm: only use fragments which were not resent as RTT samples
+            if !sent.resent {
+                let sample = now - sent.first_sent;
+                self.srtt = Some(match self.srtt {
+                    Some(srtt) => (srtt * 7 + sample) / 8,
+                    None => sample,
+                });
+            }
+        }
+    }
+
+    /// Takes the acknowledgements for the next packet, leaving the rest for the ones after it.
+    fn take_acks(pending_acks: &mut Vec<u32>) -> Vec<u32> {
+        let count = pending_acks.len().min(MAX_ACKS_PER_PACKET);
+        pending_acks.drain(..count).collect()
+    }
+
+    /// Adds a received fragment, returning the whole message once all its fragments arrived.
+    fn reassemble(&mut self, fragment: Fragment, now: Instant) -> Option<Vec<u8>> {
+        if let Some(sequence) = fragment.sequence {
+            self.pending_acks.push(sequence);
+            if !self.received.insert(sequence) {
+                return None;
+            }
+        }
+        if fragment.count <= 1 {
+            return Some(fragment.data);
+        }
+
+        let count = fragment.count as usize;
+        let reassembly = self.reassemblies.entry(fragment.message).or_insert(Reassembly {
+            fragments: vec![None; count],
+            missing: count,
+            started: now,
+            reliable: fragment.sequence.is_some(),
+        });
+        let slot = reassembly.fragments.get_mut(fragment.index as usize)?;
+        if slot.is_none() {
+            *slot = Some(fragment.data);
+            reassembly.missing -= 1;
+        }
+        if reassembly.missing > 0 {
+            return None;
+        }
+
+        let reassembly = self.reassemblies.remove(&fragment.message).unwrap();
+        Some(reassembly.fragments.into_iter().flatten().flatten().collect())
+    }
+}
+
+/// The socket of a backend, which subjects outgoing datagrams to simulated network conditions if
+/// there are any.
+struct DatagramSocket {
+    socket: UdpSocket,
+    conditioner: Option<Conditioner<(SocketAddr, Vec<u8>)>>,
+}
+
+impl DatagramSocket {
+    fn new(socket: UdpSocket) -> Self {
+        Self {
+            socket,
+            conditioner: None,
+        }
+    }
+
+    fn send(&mut self, addr: SocketAddr, packet: &Packet) {
+        let datagram = bincode::serialize(packet).unwrap();
+        debug_assert!(datagram.len() <= MAX_PACKET_SIZE, "Datagram exceeds the MTU budget");
+        match &mut self.conditioner {
+            Some(conditioner) => conditioner.submit((addr, datagram), false),
+            None => Self::send_datagram(&self.socket, addr, &datagram),
+        }
+    }
+
+    /// Sends the datagrams held back by the simulated network conditions which are due.
+    fn flush(&mut self) {
+        if let Some(conditioner) = &mut self.conditioner {
+            for (addr, datagram) in conditioner.due() {
+                Self::send_datagram(&self.socket, addr, &datagram);
+            }
+        }
+    }
+
+    fn send_datagram(socket: &UdpSocket, addr: SocketAddr, datagram: &[u8]) {
+        if let Err(e) = socket.send_to(datagram, addr) {
+            // the reliability layer takes care of lost datagrams
+            trace!("Failed to send datagram to {}: {}", addr, e);
+        }
+    }
+}
+
+/// Network backend sending messages as UDP datagrams.
+///
+/// Reliable messages are resent until acknowledged, but unlike with TCP a lost message does not
+/// hold back the ones sent after it. Messages larger than a datagram are split into fragments.
+pub struct UdpBackend {
+    socket: DatagramSocket,
+    /// Whether new peers are accepted, i.e. if this is the server side
+    accept_connections: bool,
+    connections: Vec<UdpConnection>,
+}
+
+impl UdpBackend {
+    /// Creates a backend which connects to the server at the given address.
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let server_addr = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or(io::ErrorKind::AddrNotAvailable)?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_nonblocking(true)?;
+
+        let mut backend = Self {
+            socket: DatagramSocket::new(socket),
+            accept_connections: false,
+            connections: Vec::new(),
+        };
+        let now = Instant::now();
+        let mut conn = UdpConnection::new(server_addr, ConnectionState::Connecting, now);
+        // make sure the first connection request goes out on the first poll
+        conn.last_sent = now - CONNECT_RETRY_INTERVAL;
+        backend.connections.push(conn);
+        Ok(backend)
+    }
+
+    /// Creates a backend accepting connections on the given address.
+    pub fn listen(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: DatagramSocket::new(socket),
+            accept_connections: true,
+            connections: Vec::new(),
+        })
+    }
+
+    /// Subjects the outgoing datagrams to the given network conditions.
+    ///
+    /// Unlike with a `ConditionedBackend` around this backend, datagrams really get lost,
+    /// duplicated and reordered, so that acknowledgements, resends and reassembly are exercised.
+    pub fn with_conditions(mut self, conditions: NetworkConditions) -> Self {
+        self.socket.conditioner = Some(Conditioner::new(conditions));
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.socket.local_addr()
+    }
+
+    fn handle_packet(
+        &mut self,
+        addr: SocketAddr,
+        packet: Packet,
+        now: Instant,
+        events: &mut Vec<NetworkSimulationEvent>,
+    ) {
+        let conn_index = self.connections.iter().position(|conn| conn.addr == addr);
+        match packet {
+            Packet::ConnectRequest { protocol_version } => {
+                if !self.accept_connections {
+                    return;
+                }
+                if protocol_version != UDP_PROTOCOL_VERSION {
+                    warn!("Rejecting {} with protocol version {}", addr, protocol_version);
+                    return;
+                }
+                if conn_index.is_none() {
+                    info!("Accepted a new connection from {}", addr);
+                    let conn = UdpConnection::new(addr, ConnectionState::Connected, now);
+                    self.connections.push(conn);
+                    events.push(NetworkSimulationEvent::Connect(addr));
+                }
+                // answer repeated requests as well, in case the acceptance got lost
+                self.socket.send(addr, &Packet::ConnectAccepted);
+            }
+            Packet::ConnectAccepted => {
+                if let Some(conn) = conn_index.map(|i| &mut self.connections[i]) {
+                    conn.last_received = now;
+                    if conn.state == ConnectionState::Connecting {
+                        conn.state = ConnectionState::Connected;
+                        events.push(NetworkSimulationEvent::Connect(addr));
+                    }
+                }
+            }
+            Packet::Disconnect => {
+                if let Some(i) = conn_index {
+                    let conn = self.connections.remove(i);
+                    if conn.state == ConnectionState::Connected {
+                        events.push(NetworkSimulationEvent::Disconnect(addr));
+                    }
+                }
+            }
+            Packet::Data { acks, fragment } => {
+                let conn = match conn_index.map(|i| &mut self.connections[i]) {
+                    Some(conn) if conn.state == ConnectionState::Connected => conn,
+                    _ => return,
+                };
+                conn.last_received = now;
+                for sequence in acks {
+                    conn.acknowledge(sequence, now);
+                }
+                if let Some(message) = fragment.and_then(|f| conn.reassemble(f, now)) {
+                    events.push(NetworkSimulationEvent::Message(addr, message));
+                }
+            }
+        }
+    }
+}
+
+impl NetworkBackend for UdpBackend {
+    fn send(
+        &mut self,
+        destination: SocketAddr,
+        payload: &[u8],
+        delivery: Delivery,
+    ) -> io::Result<()> {
+        let socket = &mut self.socket;
+        let conn = self
+            .connections
+            .iter_mut()
+            .find(|conn| conn.addr == destination && conn.state == ConnectionState::Connected)
+            .ok_or(io::ErrorKind::NotConnected)?;
+
+        let now = Instant::now();
+        for fragment in conn.fragment(payload, delivery) {
+            let packet = Packet::Data {
+                acks: UdpConnection::take_acks(&mut conn.pending_acks),
+                fragment: Some(fragment.clone()),
+            };
+            socket.send(conn.addr, &packet);
+
+            if let Some(sequence) = fragment.sequence {
+                conn.unacked.insert(
+                    sequence,
+                    SentFragment {
+                        fragment,
+                        first_sent: now,
+                        last_sent: now,
+                        resent: false,
+                    },
+                );
+            }
+        }
+        conn.last_sent = now;
+        Ok(())
+    }
+
+    fn peers(&self) -> Vec<SocketAddr> {
+        self.connections
+            .iter()
+            .filter(|conn| conn.state == ConnectionState::Connected)
+            .map(|conn| conn.addr)
+            .collect()
+    }
+
+    fn poll(&mut self) -> Vec<NetworkSimulationEvent> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        loop {
+            match self.socket.socket.recv_from(&mut buffer) {
+                Ok((len, addr)) => match bincode::deserialize::<Packet>(&buffer[..len]) {
+                    Ok(packet) => self.handle_packet(addr, packet, now, &mut events),
+                    Err(e) => warn!("Received malformed datagram from {}: {}", addr, e),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                // e.g. ICMP port unreachable on some platforms, the timeout handles this
+                Err(e) => trace!("Failed to receive datagram: {}", e),
+            }
+        }
+
+        let socket = &mut self.socket;
+        for conn in self.connections.iter_mut() {
+            if conn.state == ConnectionState::Connecting {
+                if now - conn.last_sent >= CONNECT_RETRY_INTERVAL {
+                    let request = Packet::ConnectRequest {
+                        protocol_version: UDP_PROTOCOL_VERSION,
+                    };
+                    socket.send(conn.addr, &request);
+                    conn.last_sent = now;
+                }
+                continue;
+            }
+
+            // resend reliable fragments which were not acknowledged in time
+            let resend_timeout = conn.resend_timeout();
+            let mut resent_any = false;
+            for sent in conn.unacked.values_mut() {
+                if now - sent.last_sent >= resend_timeout {
+                    let packet = Packet::Data {
+                        acks: UdpConnection::take_acks(&mut conn.pending_acks),
+                        fragment: Some(sent.fragment.clone()),
+                    };
+                    socket.send(conn.addr, &packet);
+                    sent.last_sent = now;
+                    sent.resent = true;
+                    resent_any = true;
+                }
+            }
+
+            // acknowledge received fragments right away and keep the connection alive
+            if !conn.pending_acks.is_empty() || now - conn.last_sent >= KEEP_ALIVE_INTERVAL {
+                let packet = Packet::Data {
+                    acks: UdpConnection::take_acks(&mut conn.pending_acks),
+                    fragment: None,
+                };
+                socket.send(conn.addr, &packet);
+                resent_any = true;
+            }
+            if resent_any {
+                conn.last_sent = now;
+            }
+
+            conn.reassemblies.retain(|_, reassembly| {
+                reassembly.reliable || now - reassembly.started < REASSEMBLY_TIMEOUT
+            });
+        }
+
+        // connections which were never established are reported as well, so a failed attempt to
+        // connect does not go unnoticed
+        let mut timed_out = Vec::new();
+        self.connections.retain(|conn| {
+            if now - conn.last_received < CONNECTION_TIMEOUT {
+                return true;
+            }
+            warn!("Connection to {} timed out", conn.addr);
+            timed_out.push(conn.addr);
+            false
+        });
+        events.extend(timed_out.into_iter().map(NetworkSimulationEvent::Disconnect));
+        self.socket.flush();
+        events
+    }
+}
+
+impl Drop for UdpBackend {
+    fn drop(&mut self) {
+        for conn in &self.connections {
+            self.socket.send(conn.addr, &Packet::Disconnect);
+        }
+        // datagrams still held back by simulated conditions get lost, the peers time out then
+        self.socket.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection() -> UdpConnection {
+        let addr = "127.0.0.1:7777".parse().unwrap();
+        UdpConnection::new(addr, ConnectionState::Connected, Instant::now())
+    }
+
+    #[test]
+    fn received_sequences_drop_duplicates() {
+        let mut received = ReceivedSequences::default();
+        assert!(received.insert(1));
+        assert!(received.insert(0));
+        assert!(!received.insert(0));
+        assert!(!received.insert(1));
+        assert_eq!(received.complete_below, 2);
+        assert!(received.above.is_empty());
+
+        assert!(received.insert(5));
+        assert!(!received.insert(5));
+        assert_eq!(received.complete_below, 2);
+        assert!(received.insert(2));
+        assert_eq!(received.complete_below, 3);
+    }
+
+    #[test]
+    fn fragments_are_reassembled_in_any_order() {
+        let mut sender = connection();
+        let mut receiver = connection();
+        let payload: Vec<u8> = (0..MAX_FRAGMENT_SIZE * 2 + 10).map(|i| i as u8).collect();
+
+        let mut fragments = sender.fragment(&payload, Delivery::Reliable);
+        assert_eq!(fragments.len(), 3);
+        assert!(fragments.iter().all(|f| f.data.len() <= MAX_FRAGMENT_SIZE));
+        fragments.reverse();
+
+        let now = Instant::now();
+        let last = fragments.pop().unwrap();
+        for fragment in fragments.iter().cloned() {
+            assert_eq!(receiver.reassemble(fragment, now), None);
+        }
+        // duplicates of received fragments are acknowledged again but otherwise ignored
+        assert_eq!(receiver.reassemble(fragments[0].clone(), now), None);
+        assert_eq!(receiver.reassemble(last, now), Some(payload));
+        assert_eq!(receiver.pending_acks.len(), 4);
+        assert!(receiver.reassemblies.is_empty());
+    }
+
+    #[test]
+    fn unreliable_fragments_have_no_sequence() {
+        let mut sender = connection();
+        let fragments = sender.fragment(&[], Delivery::Unreliable);
+        assert_eq!(fragments.len(), 1);
+        assert_eq!(fragments[0].sequence, None);
+        assert_eq!(sender.next_sequence, 0);
+    }
+
+    #[test]
+    fn acks_are_taken_in_packet_sized_batches() {
+        let mut pending: Vec<u32> = (0..MAX_ACKS_PER_PACKET as u32 + 10).collect();
+        let first = UdpConnection::take_acks(&mut pending);
+        assert_eq!(first.len(), MAX_ACKS_PER_PACKET);
+        assert_eq!(first[0], 0);
+        let second = UdpConnection::take_acks(&mut pending);
+        assert_eq!(second.len(), 10);
+        assert_eq!(second[0], MAX_ACKS_PER_PACKET as u32);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn full_packets_fit_into_the_mtu_budget() {
+        let packet = Packet::Data {
+            acks: vec![u32::MAX; MAX_ACKS_PER_PACKET],
+            fragment: Some(Fragment {
+                sequence: Some(u32::MAX),
+                message: u32::MAX,
+                index: u16::MAX,
+                count: u16::MAX,
+                data: vec![0; MAX_FRAGMENT_SIZE],
+            }),
+        };
+        assert!(bincode::serialize(&packet).unwrap().len() <= MAX_PACKET_SIZE);
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::{input::Input, prelude::*};
+
+use crate::cursor_world_coords::CursorInWorld;
+
+/// How far the color of a hovered sprite is moved towards white.
+const HIGHLIGHT_STRENGTH: f32 = 0.4;
+
+/// Mouse buttons which produce click events.
+const CLICK_BUTTONS: [MouseButton; 3] =
+    [MouseButton::Left, MouseButton::Right, MouseButton::Middle];
+
+/// The area of a sprite which reacts to the cursor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PickShape {
+    /// The largest circle fitting into the sprite, e.g. for planets and moons
+    Circle,
+    /// The whole (possibly rotated) sprite
+    Rect,
+}
+
+/// Makes a texture atlas sprite react to the cursor.
+#[derive(Clone, Copy, Debug)]
+pub struct Pickable {
+    pub shape: PickShape,
+    /// Whether the sprite is lightened while the cursor is on it
+    pub highlight: bool,
+}
+
+impl Default for Pickable {
+    fn default() -> Self {
+        Self {
+            shape: PickShape::Circle,
+            highlight: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PickingEvent {
+    /// The cursor moved onto the entity.
+    Hovered(Entity),
+    /// The cursor left the entity.
+    Unhovered(Entity),
+    /// The entity was clicked with the given mouse button.
+    Clicked(Entity, MouseButton),
+}
+
+/// The entity below the cursor, if any.
+#[derive(Default)]
+pub struct Picking {
+    hovered: Option<Entity>,
+    blocked: bool,
+    block_requested: bool,
+}
+
+impl Picking {
+    /// Returns the topmost pickable entity below the cursor.
+    pub fn hovered(&self) -> Option<Entity> {
+        self.hovered
+    }
+
+    /// Returns whether the cursor is on a UI panel covering the world.
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// Stops picking sprites for this frame, as the cursor is on a UI panel covering the world.
+    pub fn block(&mut self) {
+        self.block_requested = true;
+    }
+}
+
+/// Remembers the color of a sprite from before it was highlighted.
+struct Highlighted {
+    original: Color,
+    highlighted: Color,
+}
+
+/// This plugin finds the sprite below the cursor, reports hovering and clicking it
+/// as `PickingEvent`s and highlights it.
+pub struct PickingPlugin;
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Picking>()
+            .add_event::<PickingEvent>()
+            .add_system(picking)
+            .add_system(hover_highlight);
+    }
+}
+
+/// Returns whether the given point in world coordinates lies on the sprite.
+fn hits(
+    shape: PickShape,
+    point: Vec2,
+    trans: &GlobalTransform,
+    sprite: &TextureAtlasSprite,
+    atlas: &TextureAtlas,
+) -> bool {
+    let rect = match atlas.textures.get(sprite.index as usize) {
+        Some(rect) => rect,
+        None => return false,
+    };
+    let half_size = (rect.max - rect.min) / 2.0 * trans.scale.truncate();
+    // the point relative to the sprite, as if the sprite was not rotated
+    let offset = trans.rotation.conjugate().mul_vec3(point.extend(0.0) - trans.translation);
+    match shape {
+        PickShape::Circle => offset.truncate().length() <= half_size.x.min(half_size.y),
+        PickShape::Rect => offset.x.abs() <= half_size.x && offset.y.abs() <= half_size.y,
+    }
+}
+
+/// System determining the topmost pickable sprite below the cursor and sending events about it.
+pub fn picking(
+    mut picking: ResMut<Picking>,
+    cursor_in_world: Res<CursorInWorld>,
+    mouse_input: Res<Input<MouseButton>>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut picking_events: ResMut<Events<PickingEvent>>,
+    query: Query<(
+        Entity,
+        &Pickable,
+        &GlobalTransform,
+        &TextureAtlasSprite,
+        &Handle<TextureAtlas>,
+    )>,
+) {
+    // UI panels block the cursor again every frame it stays on them
+    picking.blocked = picking.block_requested;
+    picking.block_requested = false;
+
+    let point = cursor_in_world.position;
+    let mut topmost: Option<(Entity, f32, f32)> = None;
+    for (entity, pickable, trans, sprite, atlas) in query.iter() {
+        if picking.blocked {
+            break;
+        }
+        let atlas = match texture_atlases.get(atlas) {
+            Some(atlas) => atlas,
+            None => continue,
+        };
+        if !hits(pickable.shape, point, trans, sprite, atlas) {
+            continue;
+        }
+        // sprites in front win, otherwise the one whose center is closest to the cursor
+        let depth = trans.translation.z;
+        let distance = (trans.translation.truncate() - point).length();
+        let in_front = match topmost {
+            Some((_, top_depth, top_distance)) => {
+                depth > top_depth || (depth == top_depth && distance < top_distance)
+            }
+            None => true,
+        };
+        if in_front {
+            topmost = Some((entity, depth, distance));
+        }
+    }
+
+    let hovered = topmost.map(|(entity, _, _)| entity);
+    if hovered != picking.hovered {
+        if let Some(entity) = picking.hovered {
+            picking_events.send(PickingEvent::Unhovered(entity));
+        }
+        if let Some(entity) = hovered {
+            picking_events.send(PickingEvent::Hovered(entity));
+        }
+        picking.hovered = hovered;
+    }
+    if let Some(entity) = hovered {
+        for &button in CLICK_BUTTONS.iter() {
+            if mouse_input.just_pressed(button) {
+                picking_events.send(PickingEvent::Clicked(entity, button));
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HoverHighlightState {
+    picking_event_reader: EventReader<PickingEvent>,
+}
+
+/// System lightening pickable sprites while they are hovered.
+fn hover_highlight(
+    commands: &mut Commands,
+    mut state: Local<HoverHighlightState>,
+    picking_events: Res<Events<PickingEvent>>,
+    mut query: Query<(&Pickable, Mut<TextureAtlasSprite>, Option<&Highlighted>)>,
+) {
+    for event in state.picking_event_reader.iter(&picking_events) {
+        match *event {
+            PickingEvent::Hovered(entity) => {
+                if let Ok((pickable, mut sprite, None)) = query.get_mut(entity) {
+                    if !pickable.highlight {
+                        continue;
+                    }
+                    let original = sprite.color;
+                    let lighten = |c: f32| c + (1.0 - c) * HIGHLIGHT_STRENGTH;
+                    let highlighted = Color::rgba(
+                        lighten(original.r()),
+                        lighten(original.g()),
+                        lighten(original.b()),
+                        original.a(),
+                    );
+                    sprite.color = highlighted;
+                    commands.insert_one(
+                        entity,
+                        Highlighted {
+                            original,
+                            highlighted,
+                        },
+                    );
+                }
+            }
+            PickingEvent::Unhovered(entity) => {
+                if let Ok((_, mut sprite, Some(highlight))) = query.get_mut(entity) {
+                    // a color changed in the meantime, e.g. of an eliminated player, is kept
+                    if sprite.color == highlight.highlighted {
+                        sprite.color = highlight.original;
+                    }
+                    commands.remove_one::<Highlighted>(entity);
+                }
+            }
+            PickingEvent::Clicked(..) => {}
+        }
+    }
+}
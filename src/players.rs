@@ -0,0 +1,118 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::prelude::*;
+
+use crate::network::GameInfo;
+
+/// Colors players are shown in, indexed by player number.
+pub const PLAYER_COLORS: [Color; 8] = [
+    Color::rgb(0.3, 0.6, 1.0),
+    Color::rgb(1.0, 0.45, 0.2),
+    Color::rgb(0.4, 0.9, 0.4),
+    Color::rgb(1.0, 0.85, 0.2),
+    Color::rgb(0.8, 0.4, 1.0),
+    Color::rgb(0.2, 0.9, 0.9),
+    Color::rgb(1.0, 0.4, 0.7),
+    Color::rgb(0.85, 0.85, 0.85),
+];
+
+/// A participant of the current match.
+#[derive(Clone, Debug)]
+pub struct PlayerInfo {
+    pub name: String,
+    pub team: u8,
+    pub color: Color,
+    /// Whether the player has lost their planet
+    pub eliminated: bool,
+}
+
+/// The participants of the current match and the rules between them.
+#[derive(Default)]
+pub struct Players {
+    /// Number of the player controlling this client
+    pub local: u8,
+    players: Vec<PlayerInfo>,
+    friendly_fire: bool,
+}
+
+impl Players {
+    pub fn new(game: &GameInfo, local: u8) -> Self {
+        let players = game
+            .players
+            .iter()
+            .enumerate()
+            .map(|(index, player)| PlayerInfo {
+                name: player.name.clone(),
+                team: game.team(index),
+                color: PLAYER_COLORS[index % PLAYER_COLORS.len()],
+                eliminated: false,
+            })
+            .collect();
+        Self {
+            local,
+            players,
+            friendly_fire: game.settings.friendly_fire,
+        }
+    }
+
+    pub fn get(&self, player: u8) -> Option<&PlayerInfo> {
+        self.players.get(player as usize)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PlayerInfo> {
+        self.players.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.players.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty()
+    }
+
+    pub fn color(&self, player: u8) -> Color {
+        self.get(player).map_or(Color::WHITE, |p| p.color)
+    }
+
+    pub fn are_allies(&self, a: u8, b: u8) -> bool {
+        match (self.get(a), self.get(b)) {
+            (Some(a), Some(b)) => a.team == b.team,
+            _ => false,
+        }
+    }
+
+    /// Returns whether rockets of the attacker damage the victim's planet.
+    /// Players never hit themselves, allies only if friendly fire is enabled.
+    pub fn can_damage(&self, attacker: u8, victim: u8) -> bool {
+        attacker != victim && (self.friendly_fire || !self.are_allies(attacker, victim))
+    }
+
+    pub fn is_eliminated(&self, player: u8) -> bool {
+        self.get(player).map_or(true, |p| p.eliminated)
+    }
+
+    pub fn eliminate(&mut self, player: u8) {
+        if let Some(player) = self.players.get_mut(player as usize) {
+            player.eliminated = true;
+        }
+    }
+
+    /// Returns whether all remaining players are allies, which ends the match.
+    pub fn is_match_over(&self) -> bool {
+        let mut remaining = self.players.iter().filter(|p| !p.eliminated);
+        match remaining.next() {
+            Some(first) => remaining.all(|p| p.team == first.team),
+            None => !self.players.is_empty(),
+        }
+    }
+
+    /// Returns the team sharing the victory, once the match is over and was not a draw.
+    pub fn winning_team(&self) -> Option<u8> {
+        if !self.is_match_over() {
+            return None;
+        }
+        self.players.iter().find(|p| !p.eliminated).map(|p| p.team)
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::prelude::*;
+
+use crate::balance::Balance;
+use crate::building::BuildingType;
+use crate::components::{Expense, Moon, Owner, PlayerResources};
+use crate::lobby_screen::{ClientScreen, LobbyMaterials};
+use crate::players::Players;
+
+/// Seconds the crystal count flashes after trying to spend more than the player has.
+const FLASH_DURATION: f64 = 0.6;
+
+/// Flashes per second of the crystal count.
+const FLASH_RATE: f64 = 5.0;
+
+/// Size of the resource icons in pixels.
+const ICON_SIZE: f32 = 24.0;
+
+/// Expenses in the order of the spending breakdown, with their names.
+const EXPENSES: [(Expense, &str); 3] = [
+    (Expense::Building(BuildingType::Mining), "mining"),
+    (Expense::Building(BuildingType::Production), "production"),
+    (Expense::Rocket, "rockets"),
+];
+
+pub struct ResourceHudMaterials {
+    pink: Handle<ColorMaterial>,
+    green: Handle<ColorMaterial>,
+    transparent: Handle<ColorMaterial>,
+}
+
+impl FromResources for ResourceHudMaterials {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut materials = resources.get_mut::<Assets<ColorMaterial>>().unwrap();
+        ResourceHudMaterials {
+            pink: materials.add(Color::rgb(1.0, 0.4, 0.8).into()),
+            green: materials.add(Color::rgb(0.4, 1.0, 0.5).into()),
+            transparent: materials.add(Color::rgba(0.0, 0.0, 0.0, 0.0).into()),
+        }
+    }
+}
+
+struct ResourceHud;
+
+/// The texts of the HUD, which are updated every frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HudText {
+    Pink,
+    PinkIncome,
+    Green,
+    Spending,
+}
+
+/// This plugin shows the local player's crystals during a match, together with their income per
+/// minute and what they have spent them on.
+/// The crystal count flashes when the player tries something they cannot afford.
+pub struct ResourceHudPlugin;
+
+impl Plugin for ResourceHudPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ResourceHudMaterials>()
+            .add_system(resource_hud)
+            .add_system(resource_hud_text);
+    }
+}
+
+fn spawn_text(parent: &mut ChildBuilder, font: &Handle<Font>, text: HudText, size: f32) {
+    parent
+        .spawn(TextBundle {
+            style: Style {
+                margin: Rect::all(Val::Px(4.0)),
+                ..Default::default()
+            },
+            text: Text {
+                value: "".to_string(),
+                font: font.clone(),
+                style: TextStyle {
+                    font_size: size,
+                    color: Color::WHITE,
+                    alignment: TextAlignment::default(),
+                },
+            },
+            ..Default::default()
+        })
+        .with(text);
+}
+
+/// Spawns a row of the HUD, starting with an icon of the given crystal color.
+fn spawn_resource_row(
+    parent: &mut ChildBuilder,
+    lobby_materials: &LobbyMaterials,
+    materials: &ResourceHudMaterials,
+    icon: Handle<ColorMaterial>,
+    texts: &[(HudText, f32)],
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: materials.transparent.clone(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            parent.spawn(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(ICON_SIZE), Val::Px(ICON_SIZE)),
+                    margin: Rect::all(Val::Px(4.0)),
+                    ..Default::default()
+                },
+                material: icon,
+                ..Default::default()
+            });
+            for &(text, size) in texts {
+                spawn_text(parent, &lobby_materials.font, text, size);
+            }
+        });
+}
+
+#[derive(Default)]
+pub struct ResourceHudState {
+    root: Option<Entity>,
+}
+
+/// System showing the HUD during a match.
+fn resource_hud(
+    commands: &mut Commands,
+    mut state: Local<ResourceHudState>,
+    screen: Res<ClientScreen>,
+    lobby_materials: Res<LobbyMaterials>,
+    materials: Res<ResourceHudMaterials>,
+) {
+    match (*screen, state.root) {
+        (ClientScreen::InGame, None) => {
+            commands
+                .spawn(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            left: Val::Px(10.0),
+                            top: Val::Px(10.0),
+                            ..Default::default()
+                        },
+                        flex_direction: FlexDirection::ColumnReverse,
+                        padding: Rect::all(Val::Px(4.0)),
+                        ..Default::default()
+                    },
+                    material: lobby_materials.background.clone(),
+                    ..Default::default()
+                })
+                .with(ResourceHud)
+                .with_children(|parent| {
+                    let pink = materials.pink.clone();
+                    let texts = [(HudText::Pink, 40.0), (HudText::PinkIncome, 22.0)];
+                    spawn_resource_row(parent, &lobby_materials, &materials, pink, &texts);
+                    let green = materials.green.clone();
+                    let texts = [(HudText::Green, 40.0)];
+                    spawn_resource_row(parent, &lobby_materials, &materials, green, &texts);
+                    spawn_text(parent, &lobby_materials.font, HudText::Spending, 18.0);
+                });
+            state.root = commands.current_entity();
+        }
+        (ClientScreen::InGame, Some(_)) => {}
+        (_, Some(root)) => {
+            commands.despawn_recursive(root);
+            state.root = None;
+        }
+        (_, None) => {}
+    }
+}
+
+#[derive(Default)]
+pub struct ResourceHudTextState {
+    /// Unaffordable attempts seen so far
+    unaffordable: u32,
+    /// Time until which the crystal count flashes
+    flash_until: f64,
+}
+
+/// System updating the numbers of the HUD.
+fn resource_hud_text(
+    mut state: Local<ResourceHudTextState>,
+    time: Res<Time>,
+    balance: Res<Balance>,
+    resources: Res<PlayerResources>,
+    players: Res<Players>,
+    moon_query: Query<(&Moon, &Owner)>,
+    mut text_query: Query<(Mut<Text>, &HudText)>,
+) {
+    let now = time.seconds_since_startup;
+    // the count starts over with every match
+    if resources.unaffordable > state.unaffordable {
+        state.flash_until = now + FLASH_DURATION;
+    }
+    state.unaffordable = resources.unaffordable;
+    let flashing = now < state.flash_until && (now * FLASH_RATE).fract() < 0.5;
+
+    let mining_moons = moon_query
+        .iter()
+        .filter(|(m, o)| o.0 == players.local && m.building == Some(BuildingType::Mining))
+        .count();
+    let spending: Vec<_> = EXPENSES
+        .iter()
+        .map(|(expense, name)| {
+            let spent = resources.spent.get(expense).copied().unwrap_or(0);
+            format!("{} {}", name, spent)
+        })
+        .collect();
+
+    for (mut text, hud_text) in text_query.iter_mut() {
+        text.value = match hud_text {
+            HudText::Pink => resources.pink.to_string(),
+            HudText::PinkIncome => format!("+{:.0}/min", balance.mining_rate(mining_moons)),
+            HudText::Green => resources.green.to_string(),
+            HudText::Spending => format!("Spent: {}", spending.join(", ")),
+        };
+        if *hud_text == HudText::Pink {
+            text.style.color = if flashing {
+                Color::rgb(1.0, 0.2, 0.2)
+            } else {
+                Color::WHITE
+            };
+        }
+    }
+}
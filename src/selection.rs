@@ -0,0 +1,291 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use std::f32::consts::PI;
+
+use bevy::{input::Input, prelude::*};
+
+use crate::balance::Balance;
+use crate::building::BuildingType;
+use crate::command_bar::selected_commands;
+use crate::components::{BodyId, Moon, Owner, Planet, PLANET_HEALTH};
+use crate::input_map::{InputAction, InputActions, InputMap};
+use crate::lobby_screen::{ClientScreen, LobbyMaterials};
+use crate::network::{PendingActions, PlayerAction};
+use crate::picking::{Picking, PickingEvent};
+use crate::players::Players;
+
+/// Size of the selection ring relative to the selected body.
+const RING_SCALE: f32 = 1.25;
+
+/// Pulses per second of the selection ring.
+const RING_PULSE_SPEED: f32 = 1.5;
+
+/// The planet or moon the player selected, if any.
+#[derive(Default)]
+pub struct Selection {
+    selected: Option<Entity>,
+}
+
+impl Selection {
+    pub fn selected(&self) -> Option<Entity> {
+        self.selected
+    }
+
+    pub fn select(&mut self, entity: Option<Entity>) {
+        self.selected = entity;
+    }
+}
+
+/// A halo behind the selected body.
+struct SelectionRing;
+
+struct InfoPanel;
+
+/// This plugin lets the player select a planet or moon by clicking it, marks it with a ring
+/// and shows information about it, including the actions available for it.
+/// Clicking empty space or the deselect action clears the selection.
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Selection>()
+            .add_system(select)
+            .add_system(selection_ring)
+            .add_system(info_panel)
+            .add_system(info_panel_picking);
+    }
+}
+
+#[derive(Default)]
+pub struct SelectState {
+    picking_event_reader: EventReader<PickingEvent>,
+}
+
+fn select(
+    mut state: Local<SelectState>,
+    screen: Res<ClientScreen>,
+    picking: Res<Picking>,
+    picking_events: Res<Events<PickingEvent>>,
+    actions: Res<InputActions>,
+    mouse_input: Res<Input<MouseButton>>,
+    mut selection: ResMut<Selection>,
+    body_query: Query<(Option<&Planet>, Option<&Moon>)>,
+) {
+    if *screen != ClientScreen::InGame {
+        selection.select(None);
+        return;
+    }
+    for event in state.picking_event_reader.iter(&picking_events) {
+        if let PickingEvent::Clicked(entity, MouseButton::Left) = *event {
+            if matches!(body_query.get(entity), Ok((Some(_), _)) | Ok((_, Some(_)))) {
+                selection.select(Some(entity));
+            }
+        }
+    }
+    let clicked_nothing = mouse_input.just_pressed(MouseButton::Left)
+        && picking.hovered().is_none()
+        && !picking.is_blocked();
+    if clicked_nothing || actions.just_pressed(InputAction::Deselect) {
+        selection.select(None);
+    }
+}
+
+#[derive(Default)]
+pub struct SelectionRingState {
+    /// The selected entity and its ring
+    ring: Option<(Entity, Entity)>,
+}
+
+/// System keeping a pulsing ring behind the selected body.
+fn selection_ring(
+    commands: &mut Commands,
+    mut state: Local<SelectionRingState>,
+    time: Res<Time>,
+    selection: Res<Selection>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    body_query: Query<(&TextureAtlasSprite, &GlobalTransform)>,
+    mut ring_query: Query<(&SelectionRing, Mut<Transform>)>,
+) {
+    if state.ring.map(|(target, _)| target) != selection.selected() {
+        if let Some((_, ring)) = state.ring.take() {
+            commands.despawn(ring);
+        }
+        let target = selection.selected();
+        let body = target.and_then(|entity| body_query.get(entity).ok());
+        if let (Some(target), Some((sprite, _))) = (target, body) {
+            // the ring is the body's own sprite, enlarged and drawn behind it
+            commands
+                .spawn(SpriteSheetBundle {
+                    sprite: TextureAtlasSprite {
+                        color: Color::rgba(1.0, 1.0, 1.0, 0.5),
+                        index: sprite.index,
+                    },
+                    texture_atlas: texture_atlases.get_handle("SPRITE_SHEET"),
+                    ..Default::default()
+                })
+                .with(SelectionRing);
+            state.ring = Some((target, commands.current_entity().unwrap()));
+        }
+    }
+
+    // the ring follows the body, e.g. a moon on its orbit
+    let (target, ring) = match state.ring {
+        Some(ring) => ring,
+        None => return,
+    };
+    let body = body_query.get(target);
+    if let (Ok((_, body)), Ok((_, mut trans))) = (body, ring_query.get_mut(ring)) {
+        let phase = time.seconds_since_startup as f32 * RING_PULSE_SPEED * 2.0 * PI;
+        trans.translation = body.translation - Vec3::new(0.0, 0.0, 0.1);
+        trans.scale = body.scale * (RING_SCALE + 0.05 * phase.sin());
+    }
+}
+
+/// Returns the lines the info panel shows for the given body.
+fn info_lines(
+    players: &Players,
+    balance: &Balance,
+    owner: u8,
+    planet: Option<&Planet>,
+    moon: Option<&Moon>,
+    mining_moons: usize,
+) -> Vec<(String, Color)> {
+    let name = players.get(owner).map_or("nobody", |p| p.name.as_str());
+    let mut lines = vec![];
+    if let Some(planet) = planet {
+        lines.push((format!("Planet of {}", name), players.color(owner)));
+        lines.push((format!("HP: {} / {}", planet.health, PLANET_HEALTH), Color::WHITE));
+        let aura = planet.current_aura.map_or("none".to_string(), |a| format!("{:?}", a));
+        lines.push((format!("Aura: {}", aura), Color::WHITE));
+        let rate = balance.mining_rate(mining_moons);
+        lines.push((format!("Mining: {:.0} pink/min", rate), Color::WHITE));
+        if players.is_eliminated(owner) {
+            lines.push(("Eliminated".to_string(), Color::rgb(0.3, 0.3, 0.3)));
+        }
+    } else if let Some(moon) = moon {
+        lines.push((format!("Moon of {}", name), players.color(owner)));
+        match moon.building {
+            Some(BuildingType::Mining) => {
+                lines.push(("Building: mining".to_string(), Color::WHITE));
+                let rate = balance.mining_rate(1);
+                lines.push((format!("Mining: {:.0} pink/min", rate), Color::WHITE));
+            }
+            Some(BuildingType::Production) => {
+                lines.push(("Building: production".to_string(), Color::WHITE));
+                let cost = balance.rocket_cost;
+                lines.push((format!("Rockets: {} pink each", cost), Color::WHITE));
+            }
+            None => {
+                lines.push(("Building: none".to_string(), Color::WHITE));
+            }
+        }
+    }
+    lines
+}
+
+#[derive(Default)]
+pub struct InfoPanelState {
+    lines: Vec<(String, Color)>,
+}
+
+/// System showing information about the selected body, rebuilt whenever it changes.
+fn info_panel(
+    commands: &mut Commands,
+    mut state: Local<InfoPanelState>,
+    screen: Res<ClientScreen>,
+    selection: Res<Selection>,
+    players: Res<Players>,
+    balance: Res<Balance>,
+    input_map: Res<InputMap>,
+    pending: Res<PendingActions>,
+    materials: Res<LobbyMaterials>,
+    body_query: Query<(&Owner, Option<&Planet>, Option<&Moon>)>,
+    moon_query: Query<(&Owner, &Moon)>,
+    id_query: Query<&BodyId>,
+    panel_query: Query<(Entity, &InfoPanel)>,
+) {
+    let mut lines = match selection.selected().and_then(|entity| body_query.get(entity).ok()) {
+        Some((owner, planet, moon)) => {
+            let mining_moons = moon_query
+                .iter()
+                .filter(|(o, m)| o.0 == owner.0 && m.building == Some(BuildingType::Mining))
+                .count();
+            info_lines(&players, &balance, owner.0, planet, moon, mining_moons)
+        }
+        None => vec![],
+    };
+    // aura changes only take effect on their execution frame
+    let selected_id = selection.selected().and_then(|entity| id_query.get(entity).ok());
+    let pending_aura = pending.iter().filter_map(|p| match p.action {
+        PlayerAction::ChangeAura { aura, planet } if Some(planet) == selected_id.map(|id| id.0) => {
+            Some(aura)
+        }
+        _ => None,
+    });
+    if let Some(aura) = pending_aura.last() {
+        let aura = aura.map_or("none".to_string(), |a| format!("{:?}", a));
+        lines.push((format!("Changing aura to: {}", aura), Color::rgb(0.6, 0.6, 0.6)));
+    }
+    let actions = selected_commands(&screen, &selection, &players, &body_query);
+    if !actions.is_empty() {
+        lines.push(("Actions:".to_string(), Color::WHITE));
+        for command in actions {
+            let hotkey = input_map.hotkey(command.action());
+            lines.push((format!("{} [{}]", command.label(), hotkey), Color::WHITE));
+        }
+    }
+    if lines == state.lines {
+        return;
+    }
+
+    for (entity, _) in panel_query.iter() {
+        commands.despawn_recursive(entity);
+    }
+    if !lines.is_empty() {
+        commands
+            .spawn(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        right: Val::Px(10.0),
+                        top: Val::Px(10.0),
+                        ..Default::default()
+                    },
+                    flex_direction: FlexDirection::ColumnReverse,
+                    ..Default::default()
+                },
+                material: materials.background.clone(),
+                ..Default::default()
+            })
+            .with(InfoPanel)
+            .with(Interaction::default())
+            .with_children(|parent| {
+                for (value, color) in lines.iter() {
+                    parent.spawn(TextBundle {
+                        text: Text {
+                            value: value.clone(),
+                            font: materials.font.clone(),
+                            style: TextStyle {
+                                font_size: 22.0,
+                                color: *color,
+                                alignment: TextAlignment::default(),
+                            },
+                        },
+                        ..Default::default()
+                    });
+                }
+            });
+    }
+    state.lines = lines;
+}
+
+/// System keeping clicks on the info panel from reaching the world below it.
+fn info_panel_picking(
+    mut picking: ResMut<Picking>,
+    panel_query: Query<(&Interaction, &InfoPanel)>,
+) {
+    if panel_query.iter().any(|(interaction, _)| *interaction != Interaction::None) {
+        picking.block();
+    }
+}
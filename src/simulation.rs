@@ -0,0 +1,65 @@
+// Copyright (C) 2020 Quentin M. Kniep <hello@quentinkniep.com>
+// Distributed under terms of the MIT license.
+
+use bevy::prelude::*;
+
+use crate::balance::Balance;
+use crate::building::BuildingType;
+use crate::combat::rocket_flight;
+use crate::components::{Moon, Owner, PlayerResources};
+use crate::network::NetworkSimulationTime;
+use crate::players::Players;
+
+/// This plugin runs the game world of a match: orbiting moons, mining and flying rockets.
+/// It is shared by the game client and by clients without any graphics, like bots.
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        // the resources are reset with the server's balance once the match starts
+        app.init_resource::<Balance>()
+            .add_resource(Balance::default().starting_resources())
+            .add_system(kepler_motion)
+            .add_system(resource_mining)
+            .add_system(rocket_flight);
+    }
+}
+
+pub fn kepler_motion(
+    sim_time: Res<NetworkSimulationTime>,
+    mut query: Query<(&Moon, Mut<Transform>)>,
+) {
+    // moons follow the simulation, so they stand still while the game is paused
+    let per_frame = sim_time.per_frame_duration() as f64;
+    let t = sim_time.frame_number() as f64 * per_frame + sim_time.elapsed_duration() as f64;
+    for (moon, mut trans) in query.iter_mut() {
+        trans.translation = moon.offset(t).extend(0.0);
+    }
+}
+
+#[derive(Default)]
+pub struct ResourceMiningState {
+    /// Seconds of game time since the last yield
+    elapsed: f32,
+}
+
+/// System letting the local player's mining moons produce pink crystals.
+pub fn resource_mining(
+    mut state: Local<ResourceMiningState>,
+    time: Res<Time>,
+    sim_time: Res<NetworkSimulationTime>,
+    balance: Res<Balance>,
+    mut resources: ResMut<PlayerResources>,
+    players: Res<Players>,
+    moon_query: Query<(&Moon, &Owner)>,
+) {
+    state.elapsed += time.delta_seconds * sim_time.time_scale();
+    while balance.mining_interval > 0.0 && state.elapsed >= balance.mining_interval {
+        state.elapsed -= balance.mining_interval;
+        for (moon, owner) in moon_query.iter() {
+            if owner.0 == players.local && moon.building == Some(BuildingType::Mining) {
+                resources.pink += balance.mining_yield;
+            }
+        }
+    }
+}